@@ -1,16 +1,637 @@
+use crate::error::{Result, WatchdogError};
+use crate::models::redacted_token::RedactedToken;
 use serde::Deserialize;
-use std::sync::OnceLock;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex, RwLock};
+use std::time::Duration;
+
+pub const COMMIT_FILE_NAME: &str = "base_commit.txt";
+pub const STATUS_FILE_NAME: &str = "last_sync.json";
 
 #[derive(Deserialize, Clone)]
 pub struct KeyhouseConf {
+    #[serde(default)]
+    pub base_url: String,
+    #[serde(default)]
+    pub token: RedactedToken,
+    #[serde(default = "default_branch")]
+    pub branch: String,
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    #[serde(default = "default_max_backoff_secs")]
+    pub max_backoff_secs: u64,
+    #[serde(default)]
+    pub state_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(default = "default_home_base")]
+    pub home_base: String,
+    #[serde(default = "default_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+    #[serde(default)]
+    pub webhook_secret: Option<RedactedToken>,
+    #[serde(default = "default_fetch_concurrency")]
+    pub fetch_concurrency: usize,
+    #[serde(default)]
+    pub deletion_policy: DeletionPolicy,
+    #[serde(default = "default_disabled_retention_days")]
+    pub disabled_retention_days: u64,
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+    #[serde(default = "default_timeout_secs")]
+    pub connect_timeout_secs: u64,
+    #[serde(default = "default_timeout_secs")]
+    pub request_timeout_secs: u64,
+    #[serde(default)]
+    pub group_aliases: HashMap<String, String>,
+    #[serde(default)]
+    pub auto_create_groups: bool,
+    #[serde(default = "default_transient_retries")]
+    pub transient_retries: u32,
+    #[serde(default = "default_retry_base_delay_ms")]
+    pub retry_base_delay_ms: u64,
+    #[serde(default)]
+    pub provider_match: MatchStrategy,
+    #[serde(default)]
+    pub log_format: LogFormat,
+    #[serde(default)]
+    pub repos: Vec<RepoConf>,
+    #[serde(default = "default_max_deletions")]
+    pub max_deletions: usize,
+    #[serde(default)]
+    pub forbidden_groups: Vec<String>,
+    #[serde(default)]
+    pub max_commands_per_sec: Option<f64>,
+    #[serde(default)]
+    pub prune_orphans: bool,
+    #[serde(default)]
+    pub default_shell: Option<String>,
+    #[serde(default)]
+    pub primary_group: Option<String>,
+    #[serde(default)]
+    pub extra_useradd_args: Vec<String>,
+    /// Map a repo group named `sudo` to `wheel` when no alias is configured.
+    #[serde(default = "default_sudo_wheel_fallback")]
+    pub sudo_wheel_fallback: bool,
+    #[serde(default)]
+    pub compare_format: CompareFormat,
+    #[serde(default = "default_readiness_max_age_secs")]
+    pub readiness_max_age_secs: u64,
+    #[serde(default)]
+    pub primary_group_projects: Vec<String>,
+    #[serde(default)]
+    pub allowed_providers: Option<Vec<String>>,
+    /// "sudo", "doas", ... or "none" to run commands directly as root.
+    #[serde(default = "default_privilege_command")]
+    pub privilege_command: String,
+    /// Listings or files a full sync may fail to read and still record the
+    /// commit pointer; per-user command failures never hold it back.
+    #[serde(default = "default_full_sync_max_errors")]
+    pub full_sync_max_errors: usize,
+    #[serde(default)]
+    pub github_app: Option<GitHubAppConf>,
+    #[serde(default)]
+    pub log_requests: bool,
+    /// Sync up to the Nth most recent commit of the branch instead of its head.
+    #[serde(default)]
+    pub commit_skip: usize,
+    #[serde(default)]
+    pub protected_users: Vec<String>,
+    #[serde(default)]
+    pub rollback_on_partial_failure: bool,
+    /// The other repos of a multi-repo config, filled in by `repo_configs`.
+    #[serde(skip)]
+    pub sibling_repos: Vec<KeyhouseConf>,
+    /// One-shot syncs first sleep a random 0..startup_jitter, e.g. `"30s"`, so
+    /// a fleet started from the same cron minute does not hit GitHub at once.
+    #[serde(default, with = "humantime_serde")]
+    pub startup_jitter: Duration,
+}
+
+/// GitHub App installation used instead of `token`. `private_key` is the app's
+/// PEM key, inline or via `env:`/`file:` like `token`; installation tokens are
+/// minted from it and only kept in memory. Needs the `github-app` feature.
+#[derive(Deserialize, Clone)]
+pub struct GitHubAppConf {
+    pub app_id: u64,
+    pub installation_id: u64,
+    pub private_key: RedactedToken,
+}
+
+/// One keyhouse repository when several are synced in a single run. Each
+/// repo keeps its state under `<state_dir>/<name>`.
+#[derive(Deserialize, Clone)]
+pub struct RepoConf {
+    pub name: String,
     pub base_url: String,
-    pub token: String,
+    #[serde(default)]
+    pub token: RedactedToken,
+    #[serde(default = "default_branch")]
+    pub branch: String,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeletionPolicy {
+    #[default]
+    HardDelete,
+    Disable,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
 }
-pub static LOGGER: OnceLock<String> = OnceLock::new();
+
+/// Media type requested from the compare API. `Json` only lists the changed
+/// files, which stays small on very large compares; `Diff` is the full patch.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CompareFormat {
+    #[default]
+    Diff,
+    Json,
+}
+
+/// How a repo provider directory is matched against this host's name. `Regex`
+/// takes the first capture group of the pattern as the host's provider.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchStrategy {
+    #[default]
+    Exact,
+    Prefix,
+    Regex(String),
+}
+
+impl KeyhouseConf {
+    pub fn from_file(path: &Path) -> Result<KeyhouseConf> {
+        let contents = fs::read_to_string(path).map_err(|e| {
+            WatchdogError::Config(format!(
+                "Failed to read config file {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        let mut keyhouse_config: KeyhouseConf = toml::from_str(&contents).map_err(|e| {
+            WatchdogError::Config(format!(
+                "Failed to parse config file {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        if let Some(app) = &mut keyhouse_config.github_app {
+            app.private_key = RedactedToken::new(resolve_token(app.private_key.expose())?);
+        }
+        if let Some(secret) = &mut keyhouse_config.webhook_secret {
+            *secret = RedactedToken::new(resolve_token(secret.expose())?);
+        }
+        let uses_app = keyhouse_config.github_app.is_some();
+        if !keyhouse_config.repos.is_empty() {
+            let mut names = Vec::new();
+            for repo in &mut keyhouse_config.repos {
+                let name_is_safe = !repo.name.is_empty()
+                    && repo
+                        .name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'));
+                if !name_is_safe || names.contains(&repo.name) {
+                    return Err(WatchdogError::Config(format!(
+                        "Config file {} has an invalid or duplicate repo name {:?}",
+                        path.display(),
+                        repo.name
+                    )));
+                }
+                if repo.base_url.trim().is_empty() || (repo.token.is_blank() && !uses_app) {
+                    return Err(WatchdogError::Config(format!(
+                        "Config file {} has an empty 'base_url' or 'token' for repo {}",
+                        path.display(),
+                        repo.name
+                    )));
+                }
+                if !repo.token.is_blank() {
+                    repo.token = RedactedToken::new(resolve_token(repo.token.expose())?);
+                }
+                names.push(repo.name.clone());
+            }
+            return Ok(keyhouse_config);
+        }
+        if keyhouse_config.base_url.trim().is_empty() {
+            return Err(WatchdogError::Config(format!(
+                "Config file {} has an empty 'base_url'",
+                path.display()
+            )));
+        }
+        if keyhouse_config.token.is_blank() {
+            if uses_app {
+                return Ok(keyhouse_config);
+            }
+            return Err(WatchdogError::Config(format!(
+                "Config file {} has an empty 'token'",
+                path.display()
+            )));
+        }
+        keyhouse_config.token = RedactedToken::new(resolve_token(keyhouse_config.token.expose())?);
+        Ok(keyhouse_config)
+    }
+
+    /// The configs to sync: one per entry in `repos`, or this config alone.
+    pub fn repo_configs(&self) -> Vec<KeyhouseConf> {
+        if self.repos.is_empty() {
+            return vec![self.clone()];
+        }
+        let configs = self
+            .repos
+            .iter()
+            .map(|repo| {
+                let state_dir = self
+                    .state_dir
+                    .clone()
+                    .unwrap_or_else(|| PathBuf::from("."))
+                    .join(&repo.name);
+                KeyhouseConf {
+                    base_url: repo.base_url.clone(),
+                    token: repo.token.clone(),
+                    branch: repo.branch.clone(),
+                    state_dir: Some(state_dir),
+                    repos: Vec::new(),
+                    ..self.clone()
+                }
+            })
+            .collect::<Vec<_>>();
+        configs
+            .iter()
+            .enumerate()
+            .map(|(index, config)| KeyhouseConf {
+                sibling_repos: configs
+                    .iter()
+                    .enumerate()
+                    .filter(|(other, _)| *other != index)
+                    .map(|(_, sibling)| sibling.clone())
+                    .collect(),
+                ..config.clone()
+            })
+            .collect()
+    }
+
+    pub fn privilege_command(&self) -> Option<String> {
+        match self.privilege_command.trim() {
+            "" | "none" => None,
+            command => Some(command.to_string()),
+        }
+    }
+
+    pub fn commit_file_path(&self) -> PathBuf {
+        match &self.state_dir {
+            Some(state_dir) => state_dir.join(COMMIT_FILE_NAME),
+            None => PathBuf::from(COMMIT_FILE_NAME),
+        }
+    }
+
+    pub fn status_file_path(&self) -> PathBuf {
+        match &self.state_dir {
+            Some(state_dir) => state_dir.join(STATUS_FILE_NAME),
+            None => PathBuf::from(STATUS_FILE_NAME),
+        }
+    }
+}
+
+pub fn resolve_token(token: &str) -> Result<String> {
+    let resolved = if let Some(var) = token.strip_prefix("env:") {
+        std::env::var(var).map_err(|_| {
+            WatchdogError::Config(format!("Token environment variable {} is not set", var))
+        })?
+    } else if let Some(file) = token.strip_prefix("file:") {
+        fs::read_to_string(file).map_err(|e| {
+            WatchdogError::Config(format!("Failed to read token file {}: {}", file, e))
+        })?
+    } else {
+        return Ok(token.to_string());
+    };
+    let resolved = resolved.trim();
+    if resolved.is_empty() {
+        return Err(WatchdogError::Config(format!(
+            "Token resolved from '{}' is empty",
+            token
+        )));
+    }
+    Ok(resolved.to_string())
+}
+
+pub fn default_branch() -> String {
+    "build".to_string()
+}
+
+pub fn default_max_retries() -> u32 {
+    3
+}
+
+pub fn default_max_backoff_secs() -> u64 {
+    300
+}
+
+pub fn default_home_base() -> String {
+    "/opt/watchdog/users".to_string()
+}
+
+pub fn default_poll_interval_secs() -> u64 {
+    60
+}
+
+pub fn default_fetch_concurrency() -> usize {
+    8
+}
+
+pub fn default_disabled_retention_days() -> u64 {
+    30
+}
+
+pub fn default_user_agent() -> String {
+    "watchdog-utils".to_string()
+}
+
+pub fn default_timeout_secs() -> u64 {
+    30
+}
+
+pub fn default_transient_retries() -> u32 {
+    3
+}
+
+pub fn default_retry_base_delay_ms() -> u64 {
+    500
+}
+
+pub fn default_max_deletions() -> usize {
+    20
+}
+
+pub fn default_privilege_command() -> String {
+    "sudo".to_string()
+}
+
+pub fn default_full_sync_max_errors() -> usize {
+    3
+}
+
+pub fn default_readiness_max_age_secs() -> u64 {
+    15 * 60
+}
+
+pub fn default_sudo_wheel_fallback() -> bool {
+    true
+}
+
+#[derive(Clone)]
+pub struct UserConf {
+    pub dry_run: bool,
+    pub home_base: String,
+    pub deletion_policy: DeletionPolicy,
+    pub disabled_retention_days: u64,
+    pub group_aliases: HashMap<String, String>,
+    pub auto_create_groups: bool,
+    pub forbidden_groups: Vec<String>,
+    pub default_shell: Option<String>,
+    pub primary_group: Option<String>,
+    pub extra_useradd_args: Vec<String>,
+    pub sudo_wheel_fallback: bool,
+    pub primary_group_projects: Vec<String>,
+    pub protected_users: Vec<String>,
+    pub rollback_on_partial_failure: bool,
+}
+
+impl Default for UserConf {
+    fn default() -> Self {
+        UserConf {
+            dry_run: false,
+            home_base: default_home_base(),
+            deletion_policy: DeletionPolicy::default(),
+            disabled_retention_days: default_disabled_retention_days(),
+            group_aliases: HashMap::new(),
+            auto_create_groups: false,
+            forbidden_groups: Vec::new(),
+            default_shell: None,
+            primary_group: None,
+            extra_useradd_args: Vec::new(),
+            sudo_wheel_fallback: default_sudo_wheel_fallback(),
+            primary_group_projects: Vec::new(),
+            protected_users: Vec::new(),
+            rollback_on_partial_failure: false,
+        }
+    }
+}
+
+impl From<&KeyhouseConf> for UserConf {
+    fn from(keyhouse_config: &KeyhouseConf) -> Self {
+        UserConf {
+            dry_run: keyhouse_config.dry_run,
+            home_base: keyhouse_config.home_base.clone(),
+            deletion_policy: keyhouse_config.deletion_policy,
+            disabled_retention_days: keyhouse_config.disabled_retention_days,
+            group_aliases: keyhouse_config.group_aliases.clone(),
+            auto_create_groups: keyhouse_config.auto_create_groups,
+            forbidden_groups: keyhouse_config.forbidden_groups.clone(),
+            default_shell: keyhouse_config.default_shell.clone(),
+            primary_group: keyhouse_config.primary_group.clone(),
+            extra_useradd_args: keyhouse_config.extra_useradd_args.clone(),
+            sudo_wheel_fallback: keyhouse_config.sudo_wheel_fallback,
+            primary_group_projects: keyhouse_config.primary_group_projects.clone(),
+            protected_users: keyhouse_config.protected_users.clone(),
+            rollback_on_partial_failure: keyhouse_config.rollback_on_partial_failure,
+        }
+    }
+}
+
+pub static USER_CONF: LazyLock<RwLock<UserConf>> =
+    LazyLock::new(|| RwLock::new(UserConf::default()));
+pub fn get_user_conf() -> UserConf {
+    USER_CONF.read().expect("user conf lock poisoned").clone()
+}
+
+pub fn set_user_conf(user_conf: UserConf) {
+    *USER_CONF.write().expect("user conf lock poisoned") = user_conf;
+}
+
+/// Used until a sync sets its own target, e.g. by preflight or plan runs.
+pub const DEFAULT_LOG_TARGET: &str = "watchdog";
+
+// Targets are interned so callers can keep using `target: get_log_target()`;
+// each distinct target is leaked once, and the target only changes when a
+// sync is (re)configured.
+pub static LOGGER: RwLock<Option<&'static str>> = RwLock::new(None);
+static LOG_TARGETS: LazyLock<Mutex<HashSet<&'static str>>> =
+    LazyLock::new(|| Mutex::new(HashSet::new()));
+static LOG_COMMIT: RwLock<Option<String>> = RwLock::new(None);
 pub fn get_log_target() -> &'static str {
-    LOGGER.get().expect("log target not set").as_str()
+    LOGGER
+        .read()
+        .expect("log target lock poisoned")
+        .unwrap_or(DEFAULT_LOG_TARGET)
 }
 
 pub fn set_log_target(log_target: String) {
-    LOGGER.set(log_target).expect("log target already set");
+    let mut targets = LOG_TARGETS.lock().expect("log target lock poisoned");
+    let interned = match targets.get(log_target.as_str()) {
+        Some(interned) => *interned,
+        None => {
+            let leaked: &'static str = Box::leak(log_target.into_boxed_str());
+            targets.insert(leaked);
+            leaked
+        }
+    };
+    *LOGGER.write().expect("log target lock poisoned") = Some(interned);
+}
+
+/// The commit being synced, attached to log lines as `commit = log_commit()`;
+/// `None` outside a sync, which the JSON logger leaves out.
+pub fn log_commit() -> Option<String> {
+    LOG_COMMIT.read().expect("log target lock poisoned").clone()
+}
+
+/// Tags every following log line with the commit being synced, shortened to
+/// 12 characters; `None` clears it.
+pub fn set_log_commit(commit: Option<&str>) {
+    *LOG_COMMIT.write().expect("log target lock poisoned") =
+        commit.map(|commit| commit.get(..12).unwrap_or(commit).to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::global_lock;
+    use tempfile::TempDir;
+
+    fn write_config(dir: &TempDir, contents: &str) -> PathBuf {
+        let path = dir.path().join("watchdog.toml");
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn loads_a_sample_config_file() {
+        let dir = TempDir::new().unwrap();
+        let path = write_config(
+            &dir,
+            r#"
+base_url = "https://api.github.com/repos/octo/keyhouse/contents"
+token = "ghp_example"
+branch = "main"
+home_base = "/home"
+dry_run = true
+deletion_policy = "disable"
+startup_jitter = "1m 30s"
+
+[group_aliases]
+sudo = "wheel"
+"#,
+        );
+
+        let config = KeyhouseConf::from_file(&path).unwrap();
+
+        assert_eq!(
+            config.base_url,
+            "https://api.github.com/repos/octo/keyhouse/contents"
+        );
+        assert_eq!(config.token.expose(), "ghp_example");
+        assert_eq!(config.branch, "main");
+        assert_eq!(config.home_base, "/home");
+        assert!(config.dry_run);
+        assert_eq!(config.deletion_policy, DeletionPolicy::Disable);
+        assert_eq!(
+            config.group_aliases.get("sudo").map(String::as_str),
+            Some("wheel")
+        );
+        assert_eq!(config.max_retries, default_max_retries());
+        assert_eq!(config.startup_jitter, Duration::from_secs(90));
+    }
+
+    #[test]
+    fn rejects_a_config_without_base_url() {
+        let dir = TempDir::new().unwrap();
+        let path = write_config(&dir, "token = \"ghp_example\"\n");
+        assert!(matches!(
+            KeyhouseConf::from_file(&path),
+            Err(WatchdogError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn reports_unparsable_files_as_config_errors() {
+        let dir = TempDir::new().unwrap();
+        let path = write_config(&dir, "base_url = [\n");
+        assert!(matches!(
+            KeyhouseConf::from_file(&path),
+            Err(WatchdogError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn inline_tokens_are_used_as_is() {
+        assert_eq!(resolve_token("ghp_inline").unwrap(), "ghp_inline");
+    }
+
+    // PATH is read rather than set: mutating the environment is unsafe while
+    // other tests run.
+    #[test]
+    fn env_tokens_are_read_from_the_environment() {
+        let path = std::env::var("PATH").unwrap();
+        assert_eq!(resolve_token("env:PATH").unwrap(), path.trim());
+    }
+
+    #[test]
+    fn missing_env_token_is_a_config_error() {
+        let err = resolve_token("env:WATCHDOG_TEST_TOKEN_THAT_IS_NOT_SET").unwrap_err();
+        assert!(matches!(err, WatchdogError::Config(message) if message.contains("not set")));
+    }
+
+    #[test]
+    fn file_tokens_are_read_and_trimmed() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("token");
+        fs::write(&path, "ghp_from_file\n").unwrap();
+        assert_eq!(
+            resolve_token(&format!("file:{}", path.display())).unwrap(),
+            "ghp_from_file"
+        );
+        assert!(resolve_token(&format!("file:{}", dir.path().join("missing").display())).is_err());
+    }
+
+    #[test]
+    fn webhook_secret_is_resolved_like_the_token() {
+        let dir = TempDir::new().unwrap();
+        let secret_path = dir.path().join("webhook_secret");
+        fs::write(&secret_path, "hook-secret\n").unwrap();
+        let path = write_config(
+            &dir,
+            &format!(
+                "base_url = \"https://api.github.com/repos/o/r\"\ntoken = \"ghp_x\"\n\
+                 webhook_secret = \"file:{}\"\n",
+                secret_path.display()
+            ),
+        );
+
+        let keyhouse_config = KeyhouseConf::from_file(&path).unwrap();
+
+        let secret = keyhouse_config.webhook_secret.expect("webhook secret");
+        assert_eq!(secret.expose(), "hook-secret");
+        assert_eq!(format!("{:?}", secret), "RedactedToken(****)");
+    }
+
+    #[test]
+    fn log_target_can_be_set_again() {
+        let _guard = global_lock();
+        set_log_target("first".to_string());
+        set_log_target("second".to_string());
+        assert_eq!(get_log_target(), "second");
+        set_log_commit(Some("0123456789abcdef0123"));
+        assert_eq!(get_log_target(), "second");
+        assert_eq!(log_commit().as_deref(), Some("0123456789ab"));
+        set_log_commit(None);
+        assert_eq!(log_commit(), None);
+    }
 }
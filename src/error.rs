@@ -0,0 +1,49 @@
+use std::io;
+use std::string::FromUtf8Error;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum WatchdogError {
+    #[error("HTTP request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("GitHub rejected the credentials: {0}")]
+    GitHubAuth(String),
+    #[error("GitHub rate limit exceeded: {0}")]
+    RateLimited(String),
+    #[error("GitHub API error: {0}")]
+    GitHub(String),
+    #[error("Failed to decode content: {0}")]
+    Decode(String),
+    #[error("User command failed: {0}")]
+    UserCommand(String),
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+    #[error("Refusing to apply {count} deletions, more than max_deletions = {limit}")]
+    TooManyDeletions { count: usize, limit: usize },
+    #[error("No commit found on branch {branch} after skipping {skip}")]
+    NoCommits { branch: String, skip: usize },
+    #[error("Base commit {base} is no longer reachable (compare returned {status})")]
+    UnreachableBase { base: String, status: u16 },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl From<base64::DecodeError> for WatchdogError {
+    fn from(e: base64::DecodeError) -> Self {
+        WatchdogError::Decode(e.to_string())
+    }
+}
+
+impl From<FromUtf8Error> for WatchdogError {
+    fn from(e: FromUtf8Error) -> Self {
+        WatchdogError::Decode(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for WatchdogError {
+    fn from(e: serde_json::Error) -> Self {
+        WatchdogError::Decode(e.to_string())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, WatchdogError>;
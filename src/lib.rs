@@ -1,3 +1,13 @@
+// The crate name (watchdog_utils_II) trips non_snake_case, which
+// fails `clippy -D warnings`; renaming it would break dependents' imports.
+#![allow(non_snake_case)]
+
 pub mod config;
+pub mod error;
+pub mod logging;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 pub mod models;
 pub mod services;
+#[cfg(test)]
+mod test_support;
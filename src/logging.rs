@@ -0,0 +1,90 @@
+use log::kv::{Error, Key, Value, VisitSource, VisitValue};
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+use serde_json::{Map, Value as JsonValue};
+use std::io::Write;
+use std::sync::Once;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Emits one JSON object per record on stderr. Key-values attached to a log
+/// call (`user = ..., group = ...;`) become top-level fields; `None` values
+/// are left out.
+pub struct JsonLogger {
+    level: LevelFilter,
+}
+
+struct JsonFields<'a>(&'a mut Map<String, JsonValue>);
+
+struct IsNull(bool);
+
+impl VisitValue<'_> for IsNull {
+    fn visit_any(&mut self, _value: Value) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn visit_null(&mut self) -> Result<(), Error> {
+        self.0 = true;
+        Ok(())
+    }
+}
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
+        let mut is_null = IsNull(false);
+        value.visit(&mut is_null)?;
+        if is_null.0 {
+            return Ok(());
+        }
+        self.0
+            .insert(key.to_string(), JsonValue::String(value.to_string()));
+        Ok(())
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut fields = Map::new();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs_f64())
+            .unwrap_or(0.0);
+        fields.insert("timestamp".to_string(), timestamp.into());
+        fields.insert("level".to_string(), record.level().as_str().into());
+        fields.insert("target".to_string(), record.target().into());
+        fields.insert("message".to_string(), record.args().to_string().into());
+        let _ = record.key_values().visit(&mut JsonFields(&mut fields));
+        let line = JsonValue::Object(fields).to_string();
+        let _ = writeln!(std::io::stderr().lock(), "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+static JSON_LOGGER: Once = Once::new();
+
+pub fn init_json_logger(level: LevelFilter) -> Result<(), SetLoggerError> {
+    log::set_logger(Box::leak(Box::new(JsonLogger { level })))?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+/// Installs the JSON logger on first use; later calls are no-ops. If the host
+/// application already installed a logger, that one is kept.
+pub fn ensure_json_logger(level: LevelFilter) {
+    JSON_LOGGER.call_once(|| {
+        if let Err(e) = init_json_logger(level) {
+            log::warn!(
+                "log_format = \"json\" ignored, a logger is already installed: {}",
+                e
+            );
+        }
+    });
+}
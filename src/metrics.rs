@@ -0,0 +1,87 @@
+use crate::models::sync_report::SyncReport;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+static USERS_CREATED: AtomicU64 = AtomicU64::new(0);
+static USERS_DELETED: AtomicU64 = AtomicU64::new(0);
+static GROUP_ADDS: AtomicU64 = AtomicU64::new(0);
+static GROUP_REMOVALS: AtomicU64 = AtomicU64::new(0);
+static GROUP_ADD_FAILURES: AtomicU64 = AtomicU64::new(0);
+static SYNC_ERRORS: AtomicU64 = AtomicU64::new(0);
+static SYNC_DURATION_MICROS: AtomicU64 = AtomicU64::new(0);
+static SYNC_COUNT: AtomicU64 = AtomicU64::new(0);
+
+pub fn record_group_add_failure() {
+    GROUP_ADD_FAILURES.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_sync(report: &SyncReport, duration: Duration) {
+    USERS_CREATED.fetch_add(report.users_created as u64, Ordering::Relaxed);
+    USERS_DELETED.fetch_add(report.users_deleted as u64, Ordering::Relaxed);
+    GROUP_ADDS.fetch_add(report.groups_added as u64, Ordering::Relaxed);
+    GROUP_REMOVALS.fetch_add(report.groups_removed as u64, Ordering::Relaxed);
+    SYNC_ERRORS.fetch_add(report.errors.len() as u64, Ordering::Relaxed);
+    SYNC_DURATION_MICROS.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    SYNC_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_failed_sync(duration: Duration) {
+    SYNC_ERRORS.fetch_add(1, Ordering::Relaxed);
+    SYNC_DURATION_MICROS.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    SYNC_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn gather() -> String {
+    let mut out = String::new();
+    let counters = [
+        (
+            "watchdog_users_created_total",
+            "Users created by watchdog.",
+            &USERS_CREATED,
+        ),
+        (
+            "watchdog_users_deleted_total",
+            "Users deleted by watchdog.",
+            &USERS_DELETED,
+        ),
+        (
+            "watchdog_group_adds_total",
+            "Successful group additions.",
+            &GROUP_ADDS,
+        ),
+        (
+            "watchdog_group_removals_total",
+            "Successful group removals.",
+            &GROUP_REMOVALS,
+        ),
+        (
+            "watchdog_group_add_failures_total",
+            "Failed group additions.",
+            &GROUP_ADD_FAILURES,
+        ),
+        (
+            "watchdog_sync_errors_total",
+            "Errors recorded during syncs.",
+            &SYNC_ERRORS,
+        ),
+    ];
+    for (name, help, counter) in counters {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        let _ = writeln!(out, "{} {}", name, counter.load(Ordering::Relaxed));
+    }
+    let duration_secs = SYNC_DURATION_MICROS.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+    let _ = writeln!(
+        out,
+        "# HELP watchdog_sync_duration_seconds Time spent running syncs."
+    );
+    let _ = writeln!(out, "# TYPE watchdog_sync_duration_seconds summary");
+    let _ = writeln!(out, "watchdog_sync_duration_seconds_sum {}", duration_secs);
+    let _ = writeln!(
+        out,
+        "watchdog_sync_duration_seconds_count {}",
+        SYNC_COUNT.load(Ordering::Relaxed)
+    );
+    out
+}
@@ -0,0 +1,7 @@
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedFile {
+    pub content: String,
+    pub sha: String,
+    pub size: u64,
+    pub encoding: String,
+}
@@ -0,0 +1,97 @@
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileStatus {
+    Added,
+    Deleted,
+    Modified,
+    DeletedUser,
+    ModifiedUser,
+    Renamed,
+    /// A project's `meta.json` was added, changed or removed.
+    ModifiedMeta,
+}
+
+impl FileStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileStatus::Added => "added",
+            FileStatus::Deleted => "deleted",
+            FileStatus::Modified => "modified",
+            FileStatus::DeletedUser => "deleteduser",
+            FileStatus::ModifiedUser => "modifieduser",
+            FileStatus::Renamed => "renamed",
+            FileStatus::ModifiedMeta => "modifiedmeta",
+        }
+    }
+}
+
+impl fmt::Display for FileStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for FileStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "added" => Ok(FileStatus::Added),
+            "deleted" => Ok(FileStatus::Deleted),
+            "modified" => Ok(FileStatus::Modified),
+            "deleteduser" => Ok(FileStatus::DeletedUser),
+            "modifieduser" => Ok(FileStatus::ModifiedUser),
+            "renamed" => Ok(FileStatus::Renamed),
+            "modifiedmeta" => Ok(FileStatus::ModifiedMeta),
+            other => Err(format!("Unknown file status '{}'", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiffEntry {
+    pub provider: String,
+    pub project: String,
+    pub hash: String,
+    pub status: FileStatus,
+    /// Previous file name for `Renamed` entries; `hash` holds the new one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_hash: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_names_round_trip() {
+        for status in [
+            FileStatus::Added,
+            FileStatus::Deleted,
+            FileStatus::Modified,
+            FileStatus::DeletedUser,
+            FileStatus::ModifiedUser,
+            FileStatus::Renamed,
+            FileStatus::ModifiedMeta,
+        ] {
+            assert_eq!(status.to_string().parse::<FileStatus>(), Ok(status));
+        }
+    }
+
+    #[test]
+    fn unknown_status_is_rejected() {
+        assert!("copied".parse::<FileStatus>().is_err());
+    }
+
+    #[test]
+    fn status_serializes_lowercase() {
+        assert_eq!(
+            serde_json::to_string(&FileStatus::ModifiedUser).unwrap(),
+            "\"modifieduser\""
+        );
+    }
+}
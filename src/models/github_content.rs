@@ -2,5 +2,31 @@ use serde::Deserialize;
 
 #[derive(Debug, Deserialize)]
 pub struct GitHubContent {
+    #[serde(default)]
     pub name: String,
+    #[serde(default)]
+    pub path: String,
+    /// "file", "dir", "symlink" or "submodule" in directory listings.
+    #[serde(default, rename = "type")]
+    pub content_type: String,
+    #[serde(default)]
+    pub sha: String,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub encoding: String,
+    #[serde(default)]
+    pub content: Option<String>,
+    #[serde(default)]
+    pub git_url: Option<String>,
+}
+
+impl GitHubContent {
+    pub fn is_dir(&self) -> bool {
+        self.content_type == "dir"
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.content_type == "file"
+    }
 }
@@ -0,0 +1,69 @@
+/// What `add_user_to_group` actually changed, so callers can keep accurate
+/// counters. Dry runs report what would have happened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupAddOutcome {
+    /// The user was created and already had the group, e.g. as primary group.
+    Created,
+    AddedToGroup,
+    AlreadyMember,
+    CreatedAndAdded,
+    /// Nothing was done because the user is in `protected_users`.
+    Skipped,
+}
+
+impl GroupAddOutcome {
+    pub fn new(created: bool, added: bool) -> Self {
+        match (created, added) {
+            (true, true) => GroupAddOutcome::CreatedAndAdded,
+            (true, false) => GroupAddOutcome::Created,
+            (false, true) => GroupAddOutcome::AddedToGroup,
+            (false, false) => GroupAddOutcome::AlreadyMember,
+        }
+    }
+
+    pub fn user_created(&self) -> bool {
+        matches!(
+            self,
+            GroupAddOutcome::Created | GroupAddOutcome::CreatedAndAdded
+        )
+    }
+
+    pub fn group_added(&self) -> bool {
+        matches!(
+            self,
+            GroupAddOutcome::AddedToGroup | GroupAddOutcome::CreatedAndAdded
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outcome_reflects_what_changed() {
+        assert_eq!(
+            GroupAddOutcome::new(true, true),
+            GroupAddOutcome::CreatedAndAdded
+        );
+        assert_eq!(GroupAddOutcome::new(true, false), GroupAddOutcome::Created);
+        assert_eq!(
+            GroupAddOutcome::new(false, true),
+            GroupAddOutcome::AddedToGroup
+        );
+        assert_eq!(
+            GroupAddOutcome::new(false, false),
+            GroupAddOutcome::AlreadyMember
+        );
+    }
+
+    #[test]
+    fn counters_only_count_real_changes() {
+        let counts = |outcome: GroupAddOutcome| (outcome.user_created(), outcome.group_added());
+        assert_eq!(counts(GroupAddOutcome::CreatedAndAdded), (true, true));
+        assert_eq!(counts(GroupAddOutcome::Created), (true, false));
+        assert_eq!(counts(GroupAddOutcome::AddedToGroup), (false, true));
+        assert_eq!(counts(GroupAddOutcome::AlreadyMember), (false, false));
+        assert_eq!(counts(GroupAddOutcome::Skipped), (false, false));
+    }
+}
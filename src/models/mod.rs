@@ -1,3 +1,12 @@
 pub mod commit_info;
+pub mod decoded_file;
+pub mod diff_entry;
 pub mod github_content;
+pub mod group_add_outcome;
+pub mod preflight_report;
+pub mod project_meta;
+pub mod reconcile_result;
+pub mod redacted_token;
+pub mod sync_report;
+pub mod sync_status;
 pub mod user;
@@ -0,0 +1,25 @@
+#[derive(Debug, Clone)]
+pub struct PreflightCheck {
+    pub name: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct PreflightReport {
+    pub checks: Vec<PreflightCheck>,
+}
+
+impl PreflightReport {
+    pub fn record(&mut self, name: &str, passed: bool, detail: String) {
+        self.checks.push(PreflightCheck {
+            name: name.to_string(),
+            passed,
+            detail,
+        });
+    }
+
+    pub fn all_passed(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+}
@@ -0,0 +1,12 @@
+use serde::Deserialize;
+
+/// Project-wide provisioning settings from `access/<provider>/<project>/meta.json`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct ProjectMeta {
+    /// Members are also added to the `sudo` group (after aliasing).
+    #[serde(default)]
+    pub grant_sudo: bool,
+    /// Login shell for members, overriding `default_shell`.
+    #[serde(default)]
+    pub shell: Option<String>,
+}
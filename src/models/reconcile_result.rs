@@ -0,0 +1,12 @@
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ReconcileResult {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub failed: Vec<String>,
+}
+
+impl ReconcileResult {
+    pub fn is_noop(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.failed.is_empty()
+    }
+}
@@ -0,0 +1,57 @@
+use serde::Deserialize;
+use std::fmt;
+
+const KNOWN_PREFIXES: [&str; 5] = ["github_pat_", "ghp_", "gho_", "ghs_", "ghu_"];
+
+/// A GitHub token whose `Debug` and `Display` only show the token kind, e.g.
+/// `github_pat_****`, so logging a config or request can't leak it. Use
+/// `expose` where the real value is needed.
+#[derive(Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct RedactedToken(String);
+
+impl RedactedToken {
+    pub fn new(token: impl Into<String>) -> Self {
+        RedactedToken(token.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_blank(&self) -> bool {
+        self.0.trim().is_empty()
+    }
+}
+
+impl fmt::Display for RedactedToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let prefix = KNOWN_PREFIXES
+            .iter()
+            .find(|prefix| self.0.starts_with(*prefix))
+            .copied()
+            .unwrap_or("");
+        write!(f, "{}****", prefix)
+    }
+}
+
+impl fmt::Debug for RedactedToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RedactedToken({})", self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_and_debug_hide_the_secret() {
+        let token = RedactedToken::new("github_pat_11ABCDEFG_secretpart");
+        assert_eq!(token.to_string(), "github_pat_****");
+        assert_eq!(format!("{:?}", token), "RedactedToken(github_pat_****)");
+        assert_eq!(RedactedToken::new("ghp_secret").to_string(), "ghp_****");
+        assert_eq!(RedactedToken::new("opaque-secret").to_string(), "****");
+        assert_eq!(token.expose(), "github_pat_11ABCDEFG_secretpart");
+    }
+}
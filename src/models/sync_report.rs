@@ -0,0 +1,30 @@
+#[derive(Debug, Default, Clone)]
+pub struct SyncReport {
+    pub users_created: usize,
+    pub users_deleted: usize,
+    pub groups_added: usize,
+    pub groups_removed: usize,
+    /// Diff entries left alone because they belong to another host's provider.
+    pub skipped_other_host: usize,
+    pub errors: Vec<String>,
+    pub processed_commit: String,
+    /// Whether any user, group or key mutation was attempted, successful or
+    /// not; false for empty diffs and no-op runs.
+    pub changed: bool,
+}
+
+impl SyncReport {
+    pub fn is_clean(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn merge(&mut self, other: SyncReport) {
+        self.users_created += other.users_created;
+        self.users_deleted += other.users_deleted;
+        self.groups_added += other.groups_added;
+        self.groups_removed += other.groups_removed;
+        self.skipped_other_host += other.skipped_other_host;
+        self.errors.extend(other.errors);
+        self.changed |= other.changed;
+    }
+}
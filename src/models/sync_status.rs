@@ -0,0 +1,9 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SyncStatus {
+    /// Seconds since the Unix epoch (UTC) when the sync finished.
+    pub synced_at: u64,
+    pub commit: String,
+    pub error_count: usize,
+}
@@ -0,0 +1,200 @@
+use nix::unistd::geteuid;
+use std::io;
+use std::process::{Command, Output};
+use std::sync::{Arc, LazyLock, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub trait CommandRunner: Send + Sync {
+    fn run(&self, program: &str, args: &[&str]) -> io::Result<Output>;
+}
+
+pub struct SystemCommandRunner;
+
+impl CommandRunner for SystemCommandRunner {
+    fn run(&self, program: &str, args: &[&str]) -> io::Result<Output> {
+        Command::new(program).args(args).output()
+    }
+}
+
+// Process-global like `UserConf`, so user_service keeps its plain function
+// signatures while tests or embedders can swap in their own runner.
+static COMMAND_RUNNER: LazyLock<RwLock<Arc<dyn CommandRunner>>> =
+    LazyLock::new(|| RwLock::new(Arc::new(SystemCommandRunner)));
+
+pub fn set_command_runner(runner: Arc<dyn CommandRunner>) {
+    *COMMAND_RUNNER
+        .write()
+        .expect("command runner lock poisoned") = runner;
+}
+
+struct TokenBucket {
+    rate_per_sec: f64,
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate_per_sec: f64) -> Self {
+        let capacity = rate_per_sec.max(1.0);
+        TokenBucket {
+            rate_per_sec,
+            capacity,
+            tokens: capacity,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Takes one token, returning how long the caller must wait for it.
+    fn take(&mut self) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate_per_sec).min(self.capacity);
+        self.last_refill = now;
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate_per_sec)
+        }
+    }
+}
+
+static SUDO_LIMITER: Mutex<Option<TokenBucket>> = Mutex::new(None);
+
+/// Caps privileged invocations per second; `None` (the default) means unlimited.
+/// Re-applying the same rate keeps the current bucket.
+///
+/// The wait blocks the calling thread, like the commands themselves, so async
+/// callers run user_service on the blocking pool (`spawn_blocking`), as the
+/// sync in github_service does.
+pub fn set_command_rate(rate_per_sec: Option<f64>) {
+    let mut limiter = SUDO_LIMITER.lock().expect("command limiter lock poisoned");
+    let current = limiter.as_ref().map(|bucket| bucket.rate_per_sec);
+    let rate_per_sec = rate_per_sec.filter(|rate| *rate > 0.0);
+    if current != rate_per_sec {
+        *limiter = rate_per_sec.map(TokenBucket::new);
+    }
+}
+
+// Deliberately a blocking sleep: user_service is synchronous throughout, and
+// each command already blocks for as long as the process runs.
+fn throttle() {
+    let wait = SUDO_LIMITER
+        .lock()
+        .expect("command limiter lock poisoned")
+        .as_mut()
+        .map_or(Duration::ZERO, TokenBucket::take);
+    if !wait.is_zero() {
+        thread::sleep(wait);
+    }
+}
+
+pub fn run_command(program: &str, args: &[&str]) -> io::Result<Output> {
+    let runner = COMMAND_RUNNER
+        .read()
+        .expect("command runner lock poisoned")
+        .clone();
+    runner.run(program, args)
+}
+
+static PRIVILEGE_COMMAND: LazyLock<RwLock<Option<String>>> =
+    LazyLock::new(|| RwLock::new(Some("sudo".to_string())));
+
+/// Sets the escalation wrapper (`sudo`, `doas`, ...) prepended to privileged
+/// commands; `None` runs them directly, which requires running as root.
+/// Defaults to `sudo`.
+pub fn set_privilege_command(command: Option<String>) {
+    *PRIVILEGE_COMMAND
+        .write()
+        .expect("privilege command lock poisoned") = command;
+}
+
+pub fn privilege_command() -> Option<String> {
+    PRIVILEGE_COMMAND
+        .read()
+        .expect("privilege command lock poisoned")
+        .clone()
+}
+
+/// Fails when no escalation wrapper is configured and the process isn't root,
+/// so a misconfigured host stops before its first change instead of failing
+/// every command.
+pub fn ensure_privileged() -> io::Result<()> {
+    if privilege_command().is_none() && !geteuid().is_root() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "privilege_command is 'none' but the process is not running as root",
+        ));
+    }
+    Ok(())
+}
+
+/// Runs `args` (program first) as `user` instead of root: `<privilege_command>
+/// -u user -- args`, or `runuser -u user -- args` when already root. Used for
+/// files inside a home the user controls, where root would follow any symlink
+/// the user planted.
+pub fn run_as_user(user: &str, args: &[&str]) -> io::Result<Output> {
+    ensure_privileged()?;
+    throttle();
+    let program = privilege_command().unwrap_or_else(|| "runuser".to_string());
+    run_command(&program, &[&["-u", user, "--"], args].concat())
+}
+
+/// Runs `args` (program first) with the configured escalation wrapper.
+pub fn run_privileged(args: &[&str]) -> io::Result<Output> {
+    ensure_privileged()?;
+    throttle();
+    match privilege_command() {
+        Some(command) => run_command(&command, args),
+        None => {
+            let Some((program, rest)) = args.split_first() else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "No command given to run_privileged",
+                ));
+            };
+            run_command(program, rest)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UserConf;
+    use crate::test_support::{RecordingRunner, global_lock, install};
+
+    fn privileged_with(command: Option<&str>) -> (Arc<RecordingRunner>, io::Result<Output>) {
+        let runner = RecordingRunner::new();
+        install(&runner, UserConf::default());
+        set_privilege_command(command.map(str::to_string));
+        let result = run_privileged(&["groupadd", "proj"]);
+        set_privilege_command(Some("sudo".to_string()));
+        (runner, result)
+    }
+
+    #[test]
+    fn sudo_and_doas_wrap_privileged_commands() {
+        let _guard = global_lock();
+        for command in ["sudo", "doas"] {
+            let (runner, result) = privileged_with(Some(command));
+            assert!(result.is_ok());
+            assert_eq!(runner.commands(), [format!("{} groupadd proj", command)]);
+        }
+    }
+
+    #[test]
+    fn no_privilege_command_runs_directly_only_as_root() {
+        let _guard = global_lock();
+        let (runner, result) = privileged_with(None);
+        if geteuid().is_root() {
+            assert!(result.is_ok());
+            assert_eq!(runner.commands(), ["groupadd proj"]);
+        } else {
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+            assert!(runner.commands().is_empty());
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use crate::services::github_service::write_atomically;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+pub trait CommitStore: Send + Sync {
+    fn load(&self) -> io::Result<Option<String>>;
+    fn save(&self, commit: &str) -> io::Result<()>;
+}
+
+pub struct FileCommitStore {
+    pub path: PathBuf,
+}
+
+impl FileCommitStore {
+    pub fn new(path: PathBuf) -> Self {
+        FileCommitStore { path }
+    }
+}
+
+impl CommitStore for FileCommitStore {
+    fn load(&self) -> io::Result<Option<String>> {
+        match fs::read_to_string(&self.path) {
+            // Hand-edited files often end in a newline; a blank file means no pointer.
+            Ok(commit) => Ok(Some(commit.trim().to_string()).filter(|commit| !commit.is_empty())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn save(&self, commit: &str) -> io::Result<()> {
+        if let Some(parent) = self.path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            fs::create_dir_all(parent)?;
+        }
+        write_atomically(&self.path, commit)
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryCommitStore {
+    commit: Mutex<Option<String>>,
+}
+
+impl MemoryCommitStore {
+    pub fn new(commit: Option<String>) -> Self {
+        MemoryCommitStore {
+            commit: Mutex::new(commit),
+        }
+    }
+}
+
+impl CommitStore for MemoryCommitStore {
+    fn load(&self) -> io::Result<Option<String>> {
+        Ok(self
+            .commit
+            .lock()
+            .expect("commit store lock poisoned")
+            .clone())
+    }
+
+    fn save(&self, commit: &str) -> io::Result<()> {
+        *self.commit.lock().expect("commit store lock poisoned") = Some(commit.to_string());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn memory_store_round_trips() {
+        let store = MemoryCommitStore::new(None);
+        assert_eq!(store.load().unwrap(), None);
+        store.save("abc").unwrap();
+        assert_eq!(store.load().unwrap().as_deref(), Some("abc"));
+    }
+
+    #[test]
+    fn file_store_round_trips_and_creates_its_directory() {
+        let dir = TempDir::new().unwrap();
+        let store = FileCommitStore::new(dir.path().join("state/base_commit.txt"));
+        assert_eq!(store.load().unwrap(), None);
+        store.save("abc").unwrap();
+        assert_eq!(store.load().unwrap().as_deref(), Some("abc"));
+    }
+
+    #[test]
+    fn file_store_trims_a_newline_terminated_pointer() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("base_commit.txt");
+        fs::write(&path, "abc\n").unwrap();
+        assert_eq!(
+            FileCommitStore::new(path.clone())
+                .load()
+                .unwrap()
+                .as_deref(),
+            Some("abc")
+        );
+        fs::write(&path, " \n").unwrap();
+        assert_eq!(FileCommitStore::new(path).load().unwrap(), None);
+    }
+}
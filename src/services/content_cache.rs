@@ -0,0 +1,28 @@
+use crate::config::{get_log_target, log_commit};
+use crate::services::github_service::{is_valid_sha, write_atomically};
+use log::warn;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const CACHE_DIR_NAME: &str = "content-cache";
+
+// Entries are keyed by git blob SHA, not by repo path: access and names files
+// are edited in place, and an edited file has a new blob SHA, so it misses
+// instead of serving the old content.
+fn cache_path(cache_dir: &Path, blob_sha: &str) -> Option<PathBuf> {
+    is_valid_sha(blob_sha).then(|| cache_dir.join(blob_sha))
+}
+
+pub fn load(cache_dir: &Path, blob_sha: &str) -> Option<String> {
+    fs::read_to_string(cache_path(cache_dir, blob_sha)?).ok()
+}
+
+pub fn store(cache_dir: &Path, blob_sha: &str, contents: &str) -> io::Result<()> {
+    let Some(path) = cache_path(cache_dir, blob_sha) else {
+        warn!(target:get_log_target(), commit = log_commit(); "Not caching content under invalid blob SHA {:?}", blob_sha);
+        return Ok(());
+    };
+    fs::create_dir_all(cache_dir)?;
+    write_atomically(&path, contents)
+}
@@ -0,0 +1,151 @@
+use crate::config::{GitHubAppConf, get_log_target, log_commit};
+use crate::error::{Result, WatchdogError};
+use crate::services::github_auth::parse_token_expiration;
+use crate::services::github_client::check_status;
+use base64::Engine as _;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use log::info;
+use reqwest::Client;
+use reqwest::header::{ACCEPT, AUTHORIZATION};
+use ring::rand::SystemRandom;
+use ring::signature::{RSA_PKCS1_SHA256, RsaKeyPair};
+use serde::Deserialize;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Installation tokens live for an hour; renew them this long before expiry.
+const REFRESH_MARGIN_SECS: u64 = 5 * 60;
+
+struct InstallationToken {
+    token: String,
+    expires_at: u64,
+}
+
+#[derive(Deserialize)]
+struct AccessTokenResponse {
+    token: String,
+    expires_at: String,
+}
+
+/// Mints GitHub App installation tokens (app JWT -> installation access
+/// token) and caches the current one in memory.
+pub struct GitHubAppAuth {
+    app_id: u64,
+    installation_id: u64,
+    key: RsaKeyPair,
+    current: Mutex<Option<InstallationToken>>,
+}
+
+impl GitHubAppAuth {
+    pub fn new(app: &GitHubAppConf) -> Result<Self> {
+        let der = pem_to_der(app.private_key.expose())?;
+        let key = RsaKeyPair::from_der(&der)
+            .or_else(|_| RsaKeyPair::from_pkcs8(&der))
+            .map_err(|e| WatchdogError::Config(format!("Invalid GitHub App private key: {}", e)))?;
+        Ok(GitHubAppAuth {
+            app_id: app.app_id,
+            installation_id: app.installation_id,
+            key,
+            current: Mutex::new(None),
+        })
+    }
+
+    pub fn token(&self) -> Option<String> {
+        self.current
+            .lock()
+            .expect("app token lock poisoned")
+            .as_ref()
+            .map(|current| current.token.clone())
+    }
+
+    fn needs_refresh(&self, now: u64) -> bool {
+        self.current
+            .lock()
+            .expect("app token lock poisoned")
+            .as_ref()
+            .is_none_or(|current| current.expires_at <= now + REFRESH_MARGIN_SECS)
+    }
+
+    /// Signs the short-lived app JWT used to request installation tokens.
+    pub fn app_jwt(&self, now: u64) -> Result<String> {
+        let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"RS256","typ":"JWT"}"#);
+        // iat is backdated to tolerate clock drift; GitHub caps exp at 10 minutes.
+        let claims = URL_SAFE_NO_PAD.encode(format!(
+            r#"{{"iat":{},"exp":{},"iss":"{}"}}"#,
+            now.saturating_sub(60),
+            now + 9 * 60,
+            self.app_id
+        ));
+        let signing_input = format!("{}.{}", header, claims);
+        let mut signature = vec![0u8; self.key.public().modulus_len()];
+        self.key
+            .sign(
+                &RSA_PKCS1_SHA256,
+                &SystemRandom::new(),
+                signing_input.as_bytes(),
+                &mut signature,
+            )
+            .map_err(|_| WatchdogError::GitHubAuth("Failed to sign GitHub App JWT".to_string()))?;
+        Ok(format!(
+            "{}.{}",
+            signing_input,
+            URL_SAFE_NO_PAD.encode(signature)
+        ))
+    }
+
+    /// Mints a new installation token unless the cached one is still valid for
+    /// longer than the refresh margin.
+    pub async fn refresh(&self, http: &Client, api_root: &str) -> Result<()> {
+        let now = unix_now();
+        if !self.needs_refresh(now) {
+            return Ok(());
+        }
+        let url = format!(
+            "{}/app/installations/{}/access_tokens",
+            api_root, self.installation_id
+        );
+        let response = http
+            .post(&url)
+            .header(AUTHORIZATION, format!("Bearer {}", self.app_jwt(now)?))
+            .header(ACCEPT, "application/vnd.github+json")
+            .send()
+            .await?;
+        let minted: AccessTokenResponse = check_status(response).await?.json().await?;
+        // expires_at is ISO 8601, e.g. "2024-01-31T12:00:00Z".
+        let expiry = minted.expires_at.replacen('T', " ", 1).replace('Z', " UTC");
+        let Some(expires_at) = parse_token_expiration(&expiry) else {
+            return Err(WatchdogError::GitHubAuth(format!(
+                "Unrecognised installation token expiry '{}'",
+                minted.expires_at
+            )));
+        };
+        info!(target:get_log_target(), commit = log_commit();
+            "Minted installation token for GitHub App {} (expires {})",
+            self.app_id,
+            minted.expires_at
+        );
+        *self.current.lock().expect("app token lock poisoned") = Some(InstallationToken {
+            token: minted.token,
+            expires_at,
+        });
+        Ok(())
+    }
+}
+
+fn pem_to_der(pem: &str) -> Result<Vec<u8>> {
+    let body: String = pem
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("-----"))
+        .collect();
+    STANDARD
+        .decode(body)
+        .map_err(|e| WatchdogError::Config(format!("Invalid GitHub App private key PEM: {}", e)))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,110 @@
+#[cfg(feature = "github-app")]
+use crate::services::github_app::GitHubAppAuth;
+use reqwest::RequestBuilder;
+use reqwest::header::AUTHORIZATION;
+#[cfg(feature = "github-app")]
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub enum GitHubAuth {
+    ClassicPat(String),
+    FineGrainedPat(String),
+    #[cfg(feature = "github-app")]
+    App(Arc<GitHubAppAuth>),
+}
+
+impl GitHubAuth {
+    pub fn from_token(token: &str) -> Self {
+        if token.starts_with("github_pat_") {
+            GitHubAuth::FineGrainedPat(token.to_string())
+        } else {
+            GitHubAuth::ClassicPat(token.to_string())
+        }
+    }
+
+    /// `None` for an app whose installation token hasn't been minted yet.
+    pub fn header_value(&self) -> Option<String> {
+        match self {
+            GitHubAuth::ClassicPat(token) => Some(format!("token {}", token)),
+            GitHubAuth::FineGrainedPat(token) => Some(format!("Bearer {}", token)),
+            #[cfg(feature = "github-app")]
+            GitHubAuth::App(app) => app.token().map(|token| format!("Bearer {}", token)),
+        }
+    }
+
+    pub fn apply(&self, request: RequestBuilder) -> RequestBuilder {
+        match self.header_value() {
+            Some(value) => request.header(AUTHORIZATION, value),
+            None => request,
+        }
+    }
+}
+
+pub const OAUTH_SCOPES_HEADER: &str = "x-oauth-scopes";
+pub const TOKEN_EXPIRATION_HEADER: &str = "github-authentication-token-expiration";
+
+/// Scopes that let a classic PAT read repository contents.
+const READ_SCOPES: [&str; 2] = ["repo", "public_repo"];
+
+/// Checks an `X-OAuth-Scopes` value for a scope that can read contents.
+pub fn has_read_scope(scopes: &str) -> bool {
+    scopes
+        .split(',')
+        .map(str::trim)
+        .any(|scope| READ_SCOPES.contains(&scope))
+}
+
+/// Parses GitHub's token expiration header ("2024-01-31 12:00:00 UTC" or
+/// "2024-01-31 12:00:00 +0100") into seconds since the Unix epoch.
+pub fn parse_token_expiration(value: &str) -> Option<u64> {
+    let mut parts = value.split_whitespace();
+    let date = parts.next()?;
+    let time = parts.next()?;
+    let offset_secs = match parts.next() {
+        None | Some("UTC") => 0,
+        Some(offset) => {
+            let (sign, digits) = offset.split_at_checked(1)?;
+            if digits.len() != 4 {
+                return None;
+            }
+            let hours: i64 = digits[..2].parse().ok()?;
+            let minutes: i64 = digits[2..].parse().ok()?;
+            let secs = hours * 3600 + minutes * 60;
+            match sign {
+                "+" => secs,
+                "-" => -secs,
+                _ => return None,
+            }
+        }
+    };
+
+    let mut date_parts = date.split('-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (
+        date_parts.next()??,
+        date_parts.next()??,
+        date_parts.next()??,
+    );
+    let mut time_parts = time.split(':').map(|part| part.parse::<i64>().ok());
+    let (hour, minute, second) = (
+        time_parts.next()??,
+        time_parts.next()??,
+        time_parts.next()??,
+    );
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let local = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    u64::try_from(local - offset_secs).ok()
+}
+
+// Howard Hinnant's days-from-civil algorithm (proleptic Gregorian calendar).
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
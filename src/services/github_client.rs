@@ -0,0 +1,383 @@
+use crate::config::{CompareFormat, KeyhouseConf, MatchStrategy, get_log_target, log_commit};
+use crate::error::{Result, WatchdogError};
+use crate::services::content_cache::CACHE_DIR_NAME;
+#[cfg(feature = "github-app")]
+use crate::services::github_app::GitHubAppAuth;
+use crate::services::github_auth::GitHubAuth;
+use crate::services::provider_match::provider_allowed;
+use crate::services::repo_api::RepoApi;
+use log::{debug, warn};
+use rand::Rng;
+use reqwest::header::{ACCEPT, HeaderMap, HeaderValue, LINK, USER_AGENT};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use std::path::PathBuf;
+#[cfg(feature = "github-app")]
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub struct GitHubClient {
+    pub http: Client,
+    pub repo: RepoApi,
+    pub auth: GitHubAuth,
+    pub branch: String,
+    pub max_retries: u32,
+    pub max_backoff: Duration,
+    pub fetch_concurrency: usize,
+    pub transient_retries: u32,
+    pub retry_base_delay: Duration,
+    pub cache_dir: Option<PathBuf>,
+    pub provider_match: MatchStrategy,
+    pub compare_format: CompareFormat,
+    pub allowed_providers: Option<Vec<String>>,
+    pub log_requests: bool,
+    pub commit_skip: usize,
+}
+
+impl GitHubClient {
+    pub fn new(keyhouse_config: &KeyhouseConf) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        let user_agent = HeaderValue::from_str(&keyhouse_config.user_agent).map_err(|e| {
+            WatchdogError::Config(format!(
+                "Invalid user_agent '{}': {}",
+                keyhouse_config.user_agent, e
+            ))
+        })?;
+        headers.insert(USER_AGENT, user_agent);
+        headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("application/vnd.github.v3+json"),
+        );
+        let http = Client::builder()
+            .default_headers(headers)
+            .connect_timeout(Duration::from_secs(keyhouse_config.connect_timeout_secs))
+            .timeout(Duration::from_secs(keyhouse_config.request_timeout_secs))
+            .build()?;
+        let auth = match &keyhouse_config.github_app {
+            #[cfg(feature = "github-app")]
+            Some(app) => GitHubAuth::App(Arc::new(GitHubAppAuth::new(app)?)),
+            #[cfg(not(feature = "github-app"))]
+            Some(_) => {
+                return Err(WatchdogError::Config(
+                    "github_app is configured but this build lacks the github-app feature"
+                        .to_string(),
+                ));
+            }
+            None => GitHubAuth::from_token(keyhouse_config.token.expose()),
+        };
+        Ok(GitHubClient {
+            http,
+            repo: RepoApi::parse(&keyhouse_config.base_url)?,
+            auth,
+            branch: keyhouse_config.branch.clone(),
+            max_retries: keyhouse_config.max_retries,
+            max_backoff: Duration::from_secs(keyhouse_config.max_backoff_secs),
+            fetch_concurrency: keyhouse_config.fetch_concurrency,
+            transient_retries: keyhouse_config.transient_retries,
+            retry_base_delay: Duration::from_millis(keyhouse_config.retry_base_delay_ms),
+            cache_dir: keyhouse_config
+                .state_dir
+                .as_ref()
+                .map(|state_dir| state_dir.join(CACHE_DIR_NAME)),
+            provider_match: keyhouse_config.provider_match.clone(),
+            compare_format: keyhouse_config.compare_format,
+            allowed_providers: keyhouse_config.allowed_providers.clone(),
+            log_requests: keyhouse_config.log_requests,
+            commit_skip: keyhouse_config.commit_skip,
+        })
+    }
+
+    pub fn repo_url(&self) -> &str {
+        self.repo.repo_url()
+    }
+
+    pub fn api_root(&self) -> &str {
+        self.repo.api_root()
+    }
+
+    /// Renews short-lived credentials (GitHub App installation tokens) before
+    /// a sync; a no-op for personal access tokens.
+    pub async fn refresh_auth(&self) -> Result<()> {
+        match &self.auth {
+            #[cfg(feature = "github-app")]
+            GitHubAuth::App(app) => app.refresh(&self.http, self.api_root()).await,
+            _ => Ok(()),
+        }
+    }
+
+    pub fn handles_provider(&self, provider: &str, hostname: &str) -> bool {
+        provider_allowed(
+            provider,
+            hostname,
+            &self.provider_match,
+            self.allowed_providers.as_deref(),
+        )
+    }
+
+    pub fn get(&self, url: &str) -> RequestBuilder {
+        self.auth.apply(self.http.get(url))
+    }
+}
+
+pub async fn send_with_ratelimit(
+    client: &GitHubClient,
+    request: RequestBuilder,
+) -> Result<Response> {
+    let mut attempt = 0;
+    loop {
+        let attempt_request = request
+            .try_clone()
+            .expect("GitHub requests have no streaming body");
+        let response = if client.log_requests {
+            send_logged(attempt_request).await?
+        } else {
+            attempt_request.send().await?
+        };
+        let status = response.status();
+        if status != StatusCode::FORBIDDEN && status != StatusCode::TOO_MANY_REQUESTS {
+            return Ok(response);
+        }
+        let Some(wait) = rate_limit_wait(response.headers()) else {
+            return Ok(response);
+        };
+        if attempt >= client.max_retries {
+            warn!(target:get_log_target(), commit = log_commit();
+                "Still rate limited by GitHub after {} retries, giving up on {}",
+                attempt,
+                response.url()
+            );
+            return Err(WatchdogError::RateLimited(format!(
+                "{} after {} retries",
+                response.url(),
+                attempt
+            )));
+        }
+        let wait = wait.min(client.max_backoff);
+        attempt += 1;
+        warn!(target:get_log_target(), commit = log_commit();
+            "Rate limited by GitHub ({}), retrying in {}s (attempt {}/{})",
+            status,
+            wait.as_secs(),
+            attempt,
+            client.max_retries
+        );
+        tokio::time::sleep(wait).await;
+    }
+}
+
+// Credentials travel in the Authorization header, which is never logged; the
+// URL's query string is dropped as well in case a caller ever puts one there.
+async fn send_logged(request: RequestBuilder) -> Result<Response> {
+    let (client, request) = request.build_split();
+    let request = request?;
+    let method = request.method().clone();
+    let mut url = request.url().clone();
+    url.set_query(None);
+    let started = Instant::now();
+    let result = client.execute(request).await;
+    let outcome = match &result {
+        Ok(response) => response.status().to_string(),
+        Err(e) if e.is_timeout() => "timeout".to_string(),
+        Err(e) if e.is_connect() => "connect error".to_string(),
+        Err(_) => "request error".to_string(),
+    };
+    debug!(target:get_log_target(), commit = log_commit(); "{}", request_log_line(method.as_str(), url.as_str(), &outcome, started.elapsed()));
+    Ok(result?)
+}
+
+pub fn request_log_line(method: &str, url: &str, outcome: &str, elapsed: Duration) -> String {
+    format!(
+        "GitHub {} {} -> {} in {}ms",
+        method,
+        url,
+        outcome,
+        elapsed.as_millis()
+    )
+}
+
+// Retries 5xx responses and connection/timeout errors with jittered
+// exponential backoff; 4xx responses are returned to the caller untouched.
+pub async fn send_with_retry(client: &GitHubClient, request: RequestBuilder) -> Result<Response> {
+    let mut attempt = 0;
+    loop {
+        let retry_request = request
+            .try_clone()
+            .expect("GitHub requests have no streaming body");
+        let reason = match send_with_ratelimit(client, retry_request).await {
+            Ok(response) if response.status().is_server_error() => {
+                if attempt >= client.transient_retries {
+                    return Ok(response);
+                }
+                format!("{} returned {}", response.url(), response.status())
+            }
+            Err(WatchdogError::Http(e))
+                if (e.is_connect() || e.is_timeout()) && attempt < client.transient_retries =>
+            {
+                e.to_string()
+            }
+            result => return result,
+        };
+        let wait = backoff_delay(client.retry_base_delay, attempt).min(client.max_backoff);
+        attempt += 1;
+        warn!(target:get_log_target(), commit = log_commit();
+            "Transient GitHub failure ({}), retrying in {}ms (attempt {}/{})",
+            reason,
+            wait.as_millis(),
+            attempt,
+            client.transient_retries
+        );
+        tokio::time::sleep(wait).await;
+    }
+}
+
+fn backoff_delay(base: Duration, attempt: u32) -> Duration {
+    let ceiling = base.saturating_mul(2u32.saturating_pow(attempt));
+    let jitter = rand::rng().random_range(0.5..=1.0);
+    ceiling.mul_f64(jitter)
+}
+
+pub async fn fetch_all_pages<T: DeserializeOwned>(
+    client: &GitHubClient,
+    url: &str,
+) -> Result<Vec<T>> {
+    let mut items = Vec::new();
+    let mut next_url = Some(url.to_string());
+    while let Some(page_url) = next_url {
+        let response =
+            check_status(send_with_ratelimit(client, client.get(&page_url)).await?).await?;
+        next_url = next_page_url(response.headers());
+        items.extend(response.json::<Vec<T>>().await?);
+    }
+    Ok(items)
+}
+
+const MAX_ERROR_BODY_CHARS: usize = 500;
+
+pub async fn check_status(response: Response) -> Result<Response> {
+    if response.status().is_success() {
+        Ok(response)
+    } else {
+        Err(error_from_response(response).await)
+    }
+}
+
+/// Builds an error from a failed response, keeping GitHub's (truncated)
+/// explanation such as "Bad credentials" or "Not Found".
+pub async fn error_from_response(response: Response) -> WatchdogError {
+    let status = response.status();
+    let url = response.url().to_string();
+    let body = response.text().await.unwrap_or_default();
+    let body = body.trim();
+    let mut excerpt: String = body.chars().take(MAX_ERROR_BODY_CHARS).collect();
+    if excerpt.len() < body.len() {
+        excerpt.push_str("...");
+    }
+    let message = format!("{} returned {}: {}", url, status, excerpt);
+    if status == StatusCode::UNAUTHORIZED {
+        WatchdogError::GitHubAuth(message)
+    } else {
+        WatchdogError::GitHub(message)
+    }
+}
+
+pub fn next_page_url(headers: &HeaderMap) -> Option<String> {
+    let link = headers.get(LINK)?.to_str().ok()?;
+    link.split(',').find_map(|entry| {
+        let (url, params) = entry.split_once(';')?;
+        params
+            .split(';')
+            .any(|param| param.trim() == "rel=\"next\"")
+            .then(|| {
+                url.trim()
+                    .trim_start_matches('<')
+                    .trim_end_matches('>')
+                    .to_string()
+            })
+    })
+}
+
+fn rate_limit_wait(headers: &HeaderMap) -> Option<Duration> {
+    if let Some(retry_after) = header_u64(headers, "retry-after") {
+        return Some(Duration::from_secs(retry_after));
+    }
+    if header_u64(headers, "x-ratelimit-remaining") != Some(0) {
+        return None;
+    }
+    let reset = header_u64(headers, "x-ratelimit-reset")?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    Some(Duration::from_secs(reset.saturating_sub(now)))
+}
+
+fn header_u64(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn client_for(server: &MockServer, extra: &str) -> GitHubClient {
+        let keyhouse_config: KeyhouseConf = toml::from_str(&format!(
+            "base_url = \"{}/repos/octo/keyhouse\"\ntoken = \"ghp_test\"\n{}",
+            server.uri(),
+            extra
+        ))
+        .unwrap();
+        GitHubClient::new(&keyhouse_config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn requests_time_out_after_the_configured_timeout() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/slow"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(5)))
+            .mount(&server)
+            .await;
+        let client = client_for(&server, "request_timeout_secs = 1");
+
+        let started = Instant::now();
+        let result = client.get(&format!("{}/slow", server.uri())).send().await;
+
+        assert!(result.unwrap_err().is_timeout());
+        assert!(started.elapsed() < Duration::from_secs(4));
+    }
+
+    #[test]
+    fn request_log_line_shows_method_url_status_and_latency() {
+        assert_eq!(
+            request_log_line(
+                "GET",
+                "https://api.github.com/repos/octo/keyhouse/commits",
+                "200 OK",
+                Duration::from_millis(42)
+            ),
+            "GitHub GET https://api.github.com/repos/octo/keyhouse/commits -> 200 OK in 42ms"
+        );
+    }
+
+    #[tokio::test]
+    async fn logged_requests_still_return_the_response() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/repos/octo/keyhouse/commits"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        let client = client_for(&server, "log_requests = true");
+
+        let response = send_with_ratelimit(
+            &client,
+            client.get(&format!(
+                "{}/repos/octo/keyhouse/commits?per_page=1",
+                server.uri()
+            )),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -1,326 +1,1914 @@
-use crate::config::{KeyhouseConf, get_log_target, set_log_target};
+use crate::config::{
+    CompareFormat, DeletionPolicy, KeyhouseConf, LogFormat, get_log_target, get_user_conf,
+    log_commit, set_log_commit, set_log_target, set_user_conf,
+};
+use crate::error::{Result, WatchdogError};
+use crate::logging::ensure_json_logger;
 use crate::models::commit_info::CommitInfo;
+use crate::models::decoded_file::DecodedFile;
+use crate::models::diff_entry::{DiffEntry, FileStatus};
 use crate::models::github_content::GitHubContent;
+use crate::models::group_add_outcome::GroupAddOutcome;
+use crate::models::project_meta::ProjectMeta;
+use crate::models::sync_report::SyncReport;
+use crate::models::sync_status::SyncStatus;
+use crate::services::command_runner::{ensure_privileged, set_command_rate, set_privilege_command};
+use crate::services::commit_store::{CommitStore, FileCommitStore};
+use crate::services::content_cache;
+use crate::services::github_client::{
+    GitHubClient, check_status, error_from_response, fetch_all_pages, next_page_url,
+    send_with_ratelimit, send_with_retry,
+};
+use crate::services::repo_layout::{
+    ACCESS_DIR, NAMES_DIR, PROJECT_META_FILE, access_path, names_path, parse_access_path,
+    parse_names_path, project_meta_path, repo_path,
+};
+use crate::services::shutdown::{install_signal_handlers, shutdown_requested, wait_for_shutdown};
 use crate::services::user_service::add_user_to_group;
+use crate::services::user_service::add_user_to_groups;
+use crate::services::user_service::clear_authorized_keys;
 use crate::services::user_service::delete_user;
+use crate::services::user_service::hard_delete_user;
+use crate::services::user_service::is_disabled;
+use crate::services::user_service::list_managed_users;
+use crate::services::user_service::purge_disabled_users;
 use crate::services::user_service::remove_user_from_group;
-use anyhow::{Result, anyhow};
-use log::{error, info, warn};
-use regex::Regex;
-use reqwest::Client;
-use reqwest::header::{ACCEPT, USER_AGENT};
+use crate::services::user_service::rename_user;
+use crate::services::user_service::set_authorized_keys;
+use crate::services::user_service::set_login_shell;
+use crate::services::user_service::user_exists;
+use futures::stream::{self, StreamExt};
+use log::{LevelFilter, debug, error, info, warn};
+use percent_encoding::{NON_ALPHANUMERIC, percent_encode};
+use rand::Rng;
+use reqwest::header::ACCEPT;
+use reqwest::{Response, StatusCode};
+use serde::de::DeserializeOwned;
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
+use std::io::{self, Write};
 use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::time::MissedTickBehavior;
 
 pub async fn process_update_request(
     keyhouse_config: KeyhouseConf,
     update_log_target: &str,
     hostname: String,
-) -> Result<(), Box<dyn std::error::Error>> {
+    commit_store: &dyn CommitStore,
+) -> Result<SyncReport> {
+    startup_jitter(&keyhouse_config, &mut rand::rng()).await;
+    sync_repo(keyhouse_config, update_log_target, &hostname, commit_store).await
+}
+async fn sync_repo(
+    keyhouse_config: KeyhouseConf,
+    update_log_target: &str,
+    hostname: &str,
+    commit_store: &dyn CommitStore,
+) -> Result<SyncReport> {
+    configure_sync(&keyhouse_config, update_log_target);
+    let client = GitHubClient::new(&keyhouse_config)?;
+    timed_sync(&client, &keyhouse_config, hostname, commit_store).await
+}
+// Jitters once for the whole run rather than once per repo.
+pub async fn process_all_repos(
+    keyhouse_config: KeyhouseConf,
+    update_log_target: &str,
+    hostname: String,
+) -> Result<SyncReport> {
+    let mut report = SyncReport::default();
+    startup_jitter(&keyhouse_config, &mut rand::rng()).await;
+    for repo_config in keyhouse_config.repo_configs() {
+        let commit_store = FileCommitStore::new(repo_config.commit_file_path());
+        let base_url = repo_config.base_url.clone();
+        match sync_repo(repo_config, update_log_target, &hostname, &commit_store).await {
+            Ok(repo_report) => report.merge(repo_report),
+            Err(e) => {
+                error!(target:get_log_target(), commit = log_commit(); "Sync of {} failed: {}", base_url, e);
+                report
+                    .errors
+                    .push(format!("Sync of {} failed: {}", base_url, e));
+            }
+        }
+    }
+    Ok(report)
+}
+/// A uniformly random delay in `0..jitter`; zero when jitter is zero. Taking
+/// the RNG lets callers seed it for a reproducible delay.
+pub fn startup_delay<R: Rng + ?Sized>(jitter: Duration, rng: &mut R) -> Duration {
+    if jitter.is_zero() {
+        return Duration::ZERO;
+    }
+    Duration::from_millis(rng.random_range(0..jitter.as_millis().max(1) as u64))
+}
+async fn startup_jitter<R: Rng + ?Sized>(keyhouse_config: &KeyhouseConf, rng: &mut R) {
+    let delay = startup_delay(keyhouse_config.startup_jitter, rng);
+    if delay.is_zero() {
+        return;
+    }
+    info!(target:get_log_target(), commit = log_commit(); "Delaying startup by {}ms (startup_jitter = {:?})", delay.as_millis(), keyhouse_config.startup_jitter);
+    tokio::select! {
+        _ = tokio::time::sleep(delay) => {}
+        _ = wait_for_shutdown() => {}
+    }
+}
+pub async fn run_loop(
+    keyhouse_config: KeyhouseConf,
+    update_log_target: &str,
+    hostname: String,
+) -> Result<()> {
+    configure_sync(&keyhouse_config, update_log_target);
+    let mut repos = Vec::new();
+    for repo_config in keyhouse_config.repo_configs() {
+        let client = GitHubClient::new(&repo_config)?;
+        let commit_store = FileCommitStore::new(repo_config.commit_file_path());
+        repos.push((repo_config, client, commit_store));
+    }
+    let interval = Duration::from_secs(keyhouse_config.poll_interval_secs);
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    info!(target:get_log_target(), commit = log_commit(); "Starting sync loop every {}s", interval.as_secs());
+    install_signal_handlers();
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = wait_for_shutdown() => {}
+        }
+        if shutdown_requested() {
+            info!(target:get_log_target(), commit = log_commit(); "Sync loop stopped cleanly");
+            return Ok(());
+        }
+        for (repo_config, client, commit_store) in &repos {
+            if shutdown_requested() {
+                break;
+            }
+            let started = Instant::now();
+            match timed_sync(client, repo_config, &hostname, commit_store).await {
+                Ok(report) => {
+                    info!(target:get_log_target(), commit = log_commit();
+                        "Sync cycle for {} completed in {:.1}s at {}: {} created, {} deleted, {} group adds, {} group removals, {} skipped for other hosts, {} errors",
+                        repo_config.base_url,
+                        started.elapsed().as_secs_f64(),
+                        report.processed_commit,
+                        report.users_created,
+                        report.users_deleted,
+                        report.groups_added,
+                        report.groups_removed,
+                        report.skipped_other_host,
+                        report.errors.len()
+                    );
+                }
+                Err(e) => {
+                    error!(target:get_log_target(), commit = log_commit(); "Sync cycle for {} failed after {:.1}s: {}", repo_config.base_url, started.elapsed().as_secs_f64(), e);
+                }
+            }
+        }
+    }
+}
+async fn timed_sync(
+    client: &GitHubClient,
+    keyhouse_config: &KeyhouseConf,
+    hostname: &str,
+    commit_store: &dyn CommitStore,
+) -> Result<SyncReport> {
+    #[cfg(feature = "metrics")]
+    let started = Instant::now();
+    let result = sync_with_client(client, keyhouse_config, hostname, commit_store).await;
+    set_log_commit(None);
+    #[cfg(feature = "metrics")]
+    match &result {
+        Ok(report) => crate::metrics::record_sync(report, started.elapsed()),
+        Err(_) => crate::metrics::record_failed_sync(started.elapsed()),
+    }
+    if let Ok(report) = &result
+        && !report.processed_commit.is_empty()
+        && let Err(e) = write_sync_status(keyhouse_config, report)
+    {
+        warn!(target:get_log_target(), commit = log_commit(); "Failed to write sync status: {}", e);
+    }
+    result
+}
+fn write_sync_status(keyhouse_config: &KeyhouseConf, report: &SyncReport) -> Result<()> {
+    let status = SyncStatus {
+        synced_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0),
+        commit: report.processed_commit.clone(),
+        error_count: report.errors.len(),
+    };
+    write_atomically(
+        &keyhouse_config.status_file_path(),
+        &serde_json::to_string(&status)?,
+    )?;
+    Ok(())
+}
+pub fn last_sync_status(keyhouse_config: &KeyhouseConf) -> Option<SyncStatus> {
+    let contents = fs::read_to_string(keyhouse_config.status_file_path()).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+fn configure_sync(keyhouse_config: &KeyhouseConf, update_log_target: &str) {
     set_log_target(update_log_target.to_string());
-    let base_url = keyhouse_config.base_url.clone();
-    let token = keyhouse_config.token.clone();
+    set_user_conf(keyhouse_config.into());
+    set_command_rate(keyhouse_config.max_commands_per_sec);
+    set_privilege_command(keyhouse_config.privilege_command());
+    if keyhouse_config.log_format == LogFormat::Json {
+        ensure_json_logger(LevelFilter::Info);
+    }
+    if keyhouse_config.dry_run {
+        info!(target:get_log_target(), commit = log_commit(); "Dry run enabled, user and group changes will only be logged");
+    }
+}
+pub async fn sync_with_client(
+    client: &GitHubClient,
+    keyhouse_config: &KeyhouseConf,
+    hostname: &str,
+    commit_store: &dyn CommitStore,
+) -> Result<SyncReport> {
+    if !keyhouse_config.dry_run {
+        ensure_privileged()?;
+    }
+    client.refresh_auth().await?;
+    if let Some(state_dir) = &keyhouse_config.state_dir {
+        fs::create_dir_all(state_dir)?;
+    }
     let mut should_update_all_users = false;
-    let mut last_commit = String::new();
-    if !Path::new("base_commit.txt").exists() {
+    // Trimmed once here; every use below (compare URL, file refs) relies on it.
+    let last_commit = commit_store
+        .load()?
+        .map(|commit| commit.trim().to_string())
+        .unwrap_or_default();
+    if last_commit.is_empty() {
+        should_update_all_users = true;
+    } else if !is_valid_sha(&last_commit) {
+        warn!(target:get_log_target(), commit = log_commit(); "Stored commit is not a valid SHA, falling back to a full sync");
         should_update_all_users = true;
-    } else {
-        last_commit = fs::read_to_string("base_commit.txt")?;
-        if last_commit.trim().is_empty() {
-            should_update_all_users = true;
-        }
     }
     if should_update_all_users {
-        info!(target:get_log_target(), "No valid last commit found, updating all users...");
-        let _ = update_all_users(&base_url, &token).await;
-        let latest_commit = fetch_latest_commit(&base_url, &token).await?;
-        fs::write("base_commit.txt", &latest_commit)?;
-        return Ok(());
+        info!(target:get_log_target(), commit = log_commit(); "No valid last commit found, updating all users...");
+        return full_sync_and_save(client, keyhouse_config, hostname, commit_store).await;
+    }
+    let mut report = SyncReport::default();
+    let merge_commit = fetch_recent_commit(client).await?;
+    set_log_commit(Some(&merge_commit));
+    if merge_commit == last_commit {
+        info!(target:get_log_target(), commit = log_commit(); "No changes since last run at {}", merge_commit);
+        report.processed_commit = merge_commit;
+        return Ok(report);
     }
-    let merge_commit = fetch_recent_commit(&base_url, &token).await?;
-    let diff = fetch_diff(&base_url, &last_commit, &merge_commit, &token).await?;
-    info!(target:get_log_target(), "Fetched diff from GitHub");
-    for (cloud_provider, project, hash, status) in extract_diff_parts(&diff) {
-        info!(target:get_log_target(),
-            "Parsed diff - Project: {}, Cloud Provider: {}, Hash: {}, Status: {}",
-            project, cloud_provider, hash, status
+    let range = apply_commit_range(
+        client,
+        keyhouse_config,
+        hostname,
+        &last_commit,
+        &merge_commit,
+    )
+    .await;
+    // A force-push or recreated branch can garbage-collect the stored commit;
+    // no diff can be computed from it, so resynchronise from scratch.
+    if let Err(WatchdogError::UnreachableBase { base, status }) = &range {
+        warn!(target:get_log_target(), commit = log_commit();
+            "Stored base commit {} is no longer reachable (compare returned {}), falling back to a full sync",
+            base,
+            status
         );
-        if let Some(decoded_str) =
-            fetch_and_decode_file(&base_url, &token, &hash, &status, &last_commit).await?
+        return full_sync_and_save(client, keyhouse_config, hostname, commit_store).await;
+    }
+    let (range_report, completed) = range?;
+    report.merge(range_report);
+    if !completed {
+        return Ok(report);
+    }
+    commit_store.save(&merge_commit)?;
+    report.processed_commit = merge_commit;
+
+    Ok(report)
+}
+
+// Syncs every grant and, unless it largely failed, records the head it synced
+// as the new commit pointer.
+async fn full_sync_and_save(
+    client: &GitHubClient,
+    keyhouse_config: &KeyhouseConf,
+    hostname: &str,
+    commit_store: &dyn CommitStore,
+) -> Result<SyncReport> {
+    let mut report = SyncReport::default();
+    // Resolve the head first so the pointer never skips commits that land
+    // while the full sync is running.
+    let latest_commit = fetch_pointer_commit(client).await?;
+    set_log_commit(Some(&latest_commit));
+    let read_failures = match full_sync(client, hostname).await {
+        Ok(outcome) => {
+            report.merge(outcome.report);
+            if keyhouse_config.prune_orphans {
+                // A partial listing would make granted users look orphaned.
+                if outcome.read_failures > 0 {
+                    warn!(target:get_log_target(), commit = log_commit(); "Full sync could not read the whole repo, skipping orphan pruning");
+                } else {
+                    match sibling_granted(keyhouse_config, hostname).await {
+                        Ok(others) => {
+                            let mut granted = outcome.granted;
+                            granted.extend(others);
+                            let max_deletions = keyhouse_config.max_deletions;
+                            run_user_commands(&mut report, move |report| {
+                                prune_orphans(report, &granted, max_deletions)
+                            })
+                            .await;
+                        }
+                        Err(e) => {
+                            warn!(target:get_log_target(), commit = log_commit(); "Could not read the other configured repos, skipping orphan pruning: {}", e);
+                        }
+                    }
+                }
+            }
+            outcome.read_failures
+        }
+        Err(e) => {
+            error!(target:get_log_target(), commit = log_commit(); "Full user sync failed, leaving the commit pointer unchanged: {}", e);
+            report.errors.push(format!("Full user sync failed: {}", e));
+            return Ok(report);
+        }
+    };
+    // Grants the sync could not read would be skipped for good once the
+    // pointer moves, so leave it as is and retry next run. Failed user
+    // commands don't count: a full sync repeats them, but it never applies
+    // revocations, so holding the pointer back for them would stop those.
+    if read_failures > keyhouse_config.full_sync_max_errors {
+        warn!(target:get_log_target(), commit = log_commit();
+            "Full sync failed to read {} listings or files (full_sync_max_errors = {}), leaving the commit pointer unchanged",
+            read_failures,
+            keyhouse_config.full_sync_max_errors
+        );
+        return Ok(report);
+    }
+    commit_store.save(&latest_commit)?;
+    report.processed_commit = latest_commit;
+    Ok(report)
+}
+
+/// Applies every change between `base_commit` and `merge_commit`. The flag is
+/// false when a shutdown interrupted the range, in which case the caller must
+/// not advance its commit pointer.
+async fn apply_commit_range(
+    client: &GitHubClient,
+    keyhouse_config: &KeyhouseConf,
+    hostname: &str,
+    base_commit: &str,
+    merge_commit: &str,
+) -> Result<(SyncReport, bool)> {
+    let mut report = SyncReport::default();
+    let entries = fetch_diff_entries(client, base_commit, merge_commit).await?;
+    let deletions = entries
+        .iter()
+        .filter(|entry| matches!(entry.status, FileStatus::Deleted | FileStatus::DeletedUser))
+        .filter(|entry| {
+            entry.provider.is_empty() || client.handles_provider(&entry.provider, hostname)
+        })
+        .count();
+    if deletions > keyhouse_config.max_deletions {
+        error!(target:get_log_target(), commit = log_commit();
+            "Diff {}...{} deletes {} grants/users (max_deletions = {}), aborting without changes",
+            base_commit,
+            merge_commit,
+            deletions,
+            keyhouse_config.max_deletions
+        );
+        return Err(WatchdogError::TooManyDeletions {
+            count: deletions,
+            limit: keyhouse_config.max_deletions,
+        });
+    }
+    for entry in entries {
+        if shutdown_requested() {
+            warn!(target:get_log_target(), commit = log_commit();
+                "Shutdown requested mid-diff, leaving commit pointer at {}",
+                base_commit
+            );
+            return Ok((report, false));
+        }
+        if let Err(e) = apply_diff_entry(client, &mut report, entry, hostname, base_commit).await {
+            error!(target:get_log_target(), commit = log_commit(); "Failed to process diff entry: {}", e);
+            report
+                .errors
+                .push(format!("Failed to process diff entry: {}", e));
+        }
+    }
+    info!(target:get_log_target(), commit = log_commit(); "Processed diff successfully.");
+    if keyhouse_config.deletion_policy == DeletionPolicy::Disable {
+        match run_user_commands(&mut report, |_| purge_disabled_users())
+            .await
+            .map_err(user_command_error)
         {
-            info!(target:get_log_target(), "Decoded file for hash {}", hash);
-            if cloud_provider != hostname {
-                info!(target:get_log_target(), "not this server, skipping...");
-                continue;
+            Ok(purged) => {
+                report.users_deleted += purged.len();
+                report.changed |= !purged.is_empty();
             }
-            if status == "added" {
-                info!(target:get_log_target(), "Adding user to group...");
-                add_user_to_group(&decoded_str, &project).unwrap_or_else(|e| {
-                    error!(target:get_log_target(), "Failed to add user to group: {}", e);
-                });
-            } else if status == "deleted" {
-                info!(target:get_log_target(), "Removing user from group...");
-                remove_user_from_group(&decoded_str, &project).unwrap_or_else(|e| {
-                    error!(target:get_log_target(), "Failed to remove user from group: {}", e);
-                });
-            } else if status == "deleteduser" {
-                info!(target:get_log_target(), "Deleting user...");
-                delete_user(&decoded_str).unwrap_or_else(|e| {
-                    error!(target:get_log_target(), "Failed to delete user: {}", e);
-                });
+            Err(e) => {
+                error!(target:get_log_target(), commit = log_commit(); "Failed to purge disabled users: {}", e);
+                report
+                    .errors
+                    .push(format!("Failed to purge disabled users: {}", e));
             }
         }
     }
-    info!(target:get_log_target(), "Processed diff successfully.");
-    std::fs::write("base_commit.txt", &merge_commit)?;
+    Ok((report, true))
+}
+
+/// Replays the diff from `from_commit` to the branch head instead of the
+/// stored pointer, e.g. to backfill changes a host missed. The pointer in
+/// `commit_store` is only advanced when `persist` is set.
+pub async fn sync_from(
+    keyhouse_config: KeyhouseConf,
+    update_log_target: &str,
+    hostname: String,
+    from_commit: &str,
+    persist: bool,
+    commit_store: &dyn CommitStore,
+) -> Result<SyncReport> {
+    configure_sync(&keyhouse_config, update_log_target);
+    let from_commit = from_commit.trim();
+    if !is_valid_sha(from_commit) {
+        return Err(WatchdogError::Config(format!(
+            "Replay base {:?} is not a valid commit SHA",
+            from_commit
+        )));
+    }
+    let client = GitHubClient::new(&keyhouse_config)?;
+    client.refresh_auth().await?;
+    let merge_commit = fetch_recent_commit(&client).await?;
+    set_log_commit(Some(&merge_commit));
+    info!(target:get_log_target(), commit = log_commit();
+        "Replaying changes from {} to {} (persist: {})",
+        from_commit,
+        merge_commit,
+        persist
+    );
+    let result = apply_commit_range(
+        &client,
+        &keyhouse_config,
+        &hostname,
+        from_commit,
+        &merge_commit,
+    )
+    .await;
+    set_log_commit(None);
+    let (mut report, completed) = result?;
+    if completed && persist {
+        commit_store.save(&merge_commit)?;
+        report.processed_commit = merge_commit;
+    }
+    Ok(report)
+}
 
+/// Fetches and parses the diff from the stored commit to the branch head
+/// without applying it or moving the stored pointer, for debugging why a
+/// change did or didn't apply.
+pub async fn plan(keyhouse_config: &KeyhouseConf) -> Result<Vec<DiffEntry>> {
+    let commit_store = FileCommitStore::new(keyhouse_config.commit_file_path());
+    let last_commit = commit_store.load()?.unwrap_or_default();
+    if !is_valid_sha(&last_commit) {
+        return Err(WatchdogError::Config(format!(
+            "No valid stored commit in {}, a plan needs an incremental base",
+            keyhouse_config.commit_file_path().display()
+        )));
+    }
+    let client = GitHubClient::new(keyhouse_config)?;
+    client.refresh_auth().await?;
+    let merge_commit = fetch_recent_commit(&client).await?;
+    if merge_commit == last_commit {
+        return Ok(Vec::new());
+    }
+    fetch_diff_entries(&client, &last_commit, &merge_commit).await
+}
+
+/// `plan` rendered as pretty-printed JSON.
+pub async fn plan_json(keyhouse_config: &KeyhouseConf) -> Result<String> {
+    Ok(serde_json::to_string_pretty(&plan(keyhouse_config).await?)?)
+}
+async fn apply_diff_entry(
+    client: &GitHubClient,
+    report: &mut SyncReport,
+    entry: DiffEntry,
+    hostname: &str,
+    last_commit: &str,
+) -> Result<()> {
+    let DiffEntry {
+        provider: cloud_provider,
+        project,
+        hash,
+        status,
+        old_hash,
+    } = entry;
+    info!(target:get_log_target(), commit = log_commit(), provider = cloud_provider.as_str(), project = project.as_str(), hash = hash.as_str(), status:% = status;
+        "Parsed diff - Project: {}, Cloud Provider: {}, Hash: {}, Status: {}",
+        project, cloud_provider, hash, status
+    );
+    // Checked before fetching so other hosts' files are never downloaded.
+    if !cloud_provider.is_empty() && !client.handles_provider(&cloud_provider, hostname) {
+        info!(target:get_log_target(), commit = log_commit(); "not this server, skipping...");
+        report.skipped_other_host += 1;
+        return Ok(());
+    }
+    if status == FileStatus::ModifiedMeta {
+        return apply_project_meta_change(client, report, &cloud_provider, &project).await;
+    }
+    if let Some(decoded) = fetch_and_decode_file(client, &hash, status, last_commit).await? {
+        info!(target:get_log_target(), commit = log_commit();
+            "Decoded file for hash {} (blob {}, {} bytes)",
+            hash,
+            decoded.sha,
+            decoded.size
+        );
+        match status {
+            FileStatus::Added => {
+                info!(target:get_log_target(), commit = log_commit(); "Adding user to group...");
+                let users = parse_user_lines(&decoded.content);
+                let (batch_users, group) = (users.clone(), project.clone());
+                run_user_commands(report, move |report| {
+                    for user in &batch_users {
+                        let existed = user_exists(user).unwrap_or(true);
+                        if !add_and_record(report, user, &group, "Failed to add user to group")
+                            && !existed
+                        {
+                            roll_back_created_user(report, user);
+                        }
+                    }
+                })
+                .await;
+                if let Some(meta) =
+                    fetch_project_meta(client, report, &cloud_provider, &project, None).await
+                {
+                    apply_project_meta_blocking(report, users.clone(), meta).await;
+                }
+                apply_access_keys(client, report, &cloud_provider, &project, &hash, &users).await?;
+            }
+            FileStatus::Deleted => {
+                info!(target:get_log_target(), commit = log_commit(); "Removing user from group...");
+                let users = parse_user_lines(&decoded.content);
+                let (provider, group) = (cloud_provider.clone(), project.clone());
+                run_user_commands(report, move |report| {
+                    for user in &users {
+                        remove_and_record(report, user, &group, "Failed to remove user from group");
+                        clear_keys_and_record(report, user, &provider, &group);
+                    }
+                })
+                .await;
+            }
+            FileStatus::Modified | FileStatus::Renamed => {
+                info!(target:get_log_target(), commit = log_commit(); "Refreshing group membership...");
+                let users = parse_user_lines(&decoded.content);
+                let previous_hash = old_hash.as_deref().unwrap_or(&hash);
+                let previous_users =
+                    match fetch_and_decode_file_at(client, previous_hash, last_commit).await? {
+                        Some(previous) => parse_user_lines(&previous.content),
+                        None => Vec::new(),
+                    };
+                let (batch_users, provider, group) =
+                    (users.clone(), cloud_provider.clone(), project.clone());
+                run_user_commands(report, move |report| {
+                    for previous_user in &previous_users {
+                        if !batch_users.contains(previous_user) {
+                            remove_and_record(
+                                report,
+                                previous_user,
+                                &group,
+                                "Failed to remove previous user from group",
+                            );
+                            clear_keys_and_record(report, previous_user, &provider, &group);
+                        }
+                    }
+                    for user in &batch_users {
+                        add_and_record(report, user, &group, "Failed to add user to group");
+                    }
+                })
+                .await;
+                if let Some(meta) =
+                    fetch_project_meta(client, report, &cloud_provider, &project, None).await
+                {
+                    apply_project_meta_blocking(report, users.clone(), meta).await;
+                }
+                apply_access_keys(client, report, &cloud_provider, &project, &hash, &users).await?;
+            }
+            FileStatus::ModifiedUser => {
+                info!(target:get_log_target(), commit = log_commit(); "Updating user...");
+                let users = parse_user_lines(&decoded.content);
+                match fetch_and_decode_file_at(client, &hash, last_commit).await? {
+                    Some(previous) => {
+                        let previous_users = parse_user_lines(&previous.content);
+                        update_identity(client, report, hostname, &hash, &previous_users, &users)
+                            .await?;
+                    }
+                    None => {
+                        info!(target:get_log_target(), commit = log_commit(); "No previous identity for hash {}, nothing to update", hash);
+                    }
+                }
+            }
+            FileStatus::DeletedUser => {
+                info!(target:get_log_target(), commit = log_commit(); "Deleting user...");
+                let users = parse_user_lines(&decoded.content);
+                run_user_commands(report, move |report| {
+                    for user in &users {
+                        delete_and_record(report, user);
+                    }
+                })
+                .await;
+            }
+            FileStatus::ModifiedMeta => {}
+        }
+    }
+    Ok(())
+}
+// A names file lists one or more users. When exactly one name changed it is a
+// rename; otherwise users dropped from the file are deleted and users added to
+// it get every grant that references the identity.
+async fn update_identity(
+    client: &GitHubClient,
+    report: &mut SyncReport,
+    hostname: &str,
+    hash: &str,
+    previous_users: &[String],
+    users: &[String],
+) -> Result<()> {
+    let removed: Vec<&String> = previous_users
+        .iter()
+        .filter(|user| !users.contains(user))
+        .collect();
+    let added: Vec<&String> = users
+        .iter()
+        .filter(|user| !previous_users.contains(user))
+        .collect();
+    if let ([old_user], [new_user]) = (removed.as_slice(), added.as_slice()) {
+        let (old_user, new_user) = (old_user.to_string(), new_user.to_string());
+        run_user_commands(report, move |report| {
+            report.changed = true;
+            if let Err(e) = rename_user(&old_user, &new_user).map_err(user_command_error) {
+                error!(target:get_log_target(), commit = log_commit(); "Failed to update user: {}", e);
+                report.errors.push(format!(
+                    "Failed to rename user '{}' to '{}': {}",
+                    old_user, new_user, e
+                ));
+            }
+        })
+        .await;
+        return Ok(());
+    }
+    let removed: Vec<String> = removed.into_iter().cloned().collect();
+    run_user_commands(report, move |report| {
+        for user in &removed {
+            delete_and_record(report, user);
+        }
+    })
+    .await;
+    if added.is_empty() {
+        return Ok(());
+    }
+    let added: Vec<String> = added.into_iter().cloned().collect();
+    let projects = projects_granting(client, hostname, hash).await?;
+    run_user_commands(report, move |report| {
+        for project in &projects {
+            for user in &added {
+                add_and_record(report, user, project, "Failed to add user to group");
+            }
+        }
+    })
+    .await;
+    Ok(())
+}
+// Projects on this host with an access file for `hash`.
+async fn projects_granting(
+    client: &GitHubClient,
+    hostname: &str,
+    hash: &str,
+) -> Result<BTreeSet<String>> {
+    let mut projects = BTreeSet::new();
+    let providers: Vec<GitHubContent> = list_repo_dir(client, &[ACCESS_DIR]).await?;
+    for provider in providers.iter().filter(|entry| entry.is_dir()) {
+        if !client.handles_provider(&provider.name, hostname) {
+            continue;
+        }
+        let provider_projects: Vec<GitHubContent> =
+            list_repo_dir(client, &[ACCESS_DIR, &provider.name]).await?;
+        for project in provider_projects.iter().filter(|entry| entry.is_dir()) {
+            let entries: Vec<GitHubContent> =
+                list_repo_dir(client, &[ACCESS_DIR, &provider.name, &project.name]).await?;
+            if entries
+                .iter()
+                .any(|entry| entry.is_file() && entry.name == hash)
+            {
+                projects.insert(project.name.clone());
+            }
+        }
+    }
+    Ok(projects)
+}
+// User commands block for as long as each process runs, and with
+// max_commands_per_sec set the rate limiter sleeps between them. Each batch
+// therefore runs on tokio's blocking pool, so the (current-thread) runtime
+// keeps answering health probes, signals and webhooks meanwhile.
+async fn run_user_commands<T: Send + 'static>(
+    report: &mut SyncReport,
+    apply: impl FnOnce(&mut SyncReport) -> T + Send + 'static,
+) -> T {
+    let mut batch_report = std::mem::take(report);
+    let (batch_report, result) = tokio::task::spawn_blocking(move || {
+        let result = apply(&mut batch_report);
+        (batch_report, result)
+    })
+    .await
+    .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()));
+    *report = batch_report;
+    result
+}
+// user_service reports io::Error; on the sync side its failures are user
+// command errors.
+fn user_command_error(e: io::Error) -> WatchdogError {
+    WatchdogError::UserCommand(e.to_string())
+}
+fn delete_and_record(report: &mut SyncReport, user: &str) {
+    report.changed = true;
+    match delete_user(user).map_err(user_command_error) {
+        Ok(()) => report.users_deleted += 1,
+        Err(e) => {
+            error!(target:get_log_target(), commit = log_commit(); "Failed to delete user: {}", e);
+            report
+                .errors
+                .push(format!("Failed to delete user '{}': {}", user, e));
+        }
+    }
+}
+// Returns whether the user ended up in the group.
+fn add_and_record(report: &mut SyncReport, user: &str, group: &str, failure_message: &str) -> bool {
+    match add_user_to_group(user, group).map_err(user_command_error) {
+        Ok(outcome) => {
+            record_group_add(report, outcome);
+            true
+        }
+        Err(e) => {
+            error!(target:get_log_target(), commit = log_commit(); "{}: {}", failure_message, e);
+            #[cfg(feature = "metrics")]
+            crate::metrics::record_group_add_failure();
+            report.errors.push(format!(
+                "Failed to add user '{}' to group '{}': {}",
+                user, group, e
+            ));
+            false
+        }
+    }
+}
+// With rollback_on_partial_failure set, an account created for a grant whose
+// group add then failed is deleted again rather than left in no groups.
+fn roll_back_created_user(report: &mut SyncReport, user: &str) {
+    if !get_user_conf().rollback_on_partial_failure || !user_exists(user).unwrap_or(false) {
+        return;
+    }
+    warn!(target:get_log_target(), commit = log_commit(); "Rolling back half-provisioned user '{}'", user);
+    if let Err(e) = hard_delete_user(user).map_err(user_command_error) {
+        error!(target:get_log_target(), commit = log_commit(); "Failed to roll back user '{}': {}", user, e);
+        report.errors.push(format!(
+            "Failed to roll back half-provisioned user '{}': {}",
+            user, e
+        ));
+    }
+}
+fn record_group_add(report: &mut SyncReport, outcome: GroupAddOutcome) {
+    if outcome.group_added() || outcome.user_created() {
+        report.changed = true;
+    }
+    if outcome.group_added() {
+        report.groups_added += 1;
+    }
+    if outcome.user_created() {
+        report.users_created += 1;
+    }
+}
+fn remove_and_record(report: &mut SyncReport, user: &str, group: &str, failure_message: &str) {
+    report.changed = true;
+    match remove_user_from_group(user, group).map_err(user_command_error) {
+        Ok(()) => report.groups_removed += 1,
+        Err(e) => {
+            error!(target:get_log_target(), commit = log_commit(); "{}: {}", failure_message, e);
+            report.errors.push(format!(
+                "Failed to remove user '{}' from group '{}': {}",
+                user, group, e
+            ));
+        }
+    }
+}
+// Keys are only installed for single-user files; sharing one key set across
+// several accounts would let any key holder log in as all of them.
+async fn apply_access_keys(
+    client: &GitHubClient,
+    report: &mut SyncReport,
+    provider: &str,
+    project: &str,
+    hash: &str,
+    users: &[String],
+) -> Result<()> {
+    let [user] = users else {
+        if users.len() > 1 {
+            info!(target:get_log_target(), commit = log_commit(); "Access file {} lists {} users, not installing shared keys", hash, users.len());
+        }
+        return Ok(());
+    };
+    let keys = fetch_access_keys(client, provider, project, hash, &client.branch).await?;
+    let (user, source, hash) = (
+        user.clone(),
+        keys_source(provider, project),
+        hash.to_string(),
+    );
+    run_user_commands(report, move |report| {
+        record_access_keys(report, &user, &source, &hash, keys)
+    })
+    .await;
     Ok(())
 }
-pub async fn fetch_recent_commit(
-    base_url: &str,
-    token: &str,
-) -> Result<String, Box<dyn std::error::Error>> {
-    let client = reqwest::Client::new();
-
-    let clean_base: &str = base_url.trim_end_matches("/contents");
-    let url = format!("{}/commits?sha=build&per_page=1", clean_base);
-    let commits: Vec<CommitInfo> = client
-        .get(&url)
-        .bearer_auth(token)
-        .header(USER_AGENT, "rust-webhook-server")
-        .header(ACCEPT, "application/vnd.github.v3+json")
-        .send()
+// Each grant's keys live in their own block of authorized_keys, so a user in
+// several projects keeps every project's keys and losing one grant only
+// removes that grant's.
+fn keys_source(provider: &str, project: &str) -> String {
+    format!("{}/{}", provider, project)
+}
+fn record_access_keys(
+    report: &mut SyncReport,
+    user: &str,
+    source: &str,
+    hash: &str,
+    keys: Option<String>,
+) {
+    match keys {
+        Some(keys) if !keys.trim().is_empty() => {
+            report.changed = true;
+            if let Err(e) = set_authorized_keys(user, source, &keys).map_err(user_command_error) {
+                error!(target:get_log_target(), commit = log_commit(); "Failed to set authorized keys: {}", e);
+                report.errors.push(format!(
+                    "Failed to set authorized keys for '{}': {}",
+                    user, e
+                ));
+            }
+        }
+        _ => {
+            info!(target:get_log_target(), commit = log_commit(); "No keys in access file {}, leaving authorized keys untouched", hash);
+        }
+    }
+}
+fn clear_keys_and_record(report: &mut SyncReport, user: &str, provider: &str, project: &str) {
+    report.changed = true;
+    if let Err(e) =
+        clear_authorized_keys(user, &keys_source(provider, project)).map_err(user_command_error)
+    {
+        error!(target:get_log_target(), commit = log_commit(); "Failed to clear authorized keys: {}", e);
+        report.errors.push(format!(
+            "Failed to clear authorized keys for '{}': {}",
+            user, e
+        ));
+    }
+}
+pub fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let mut tmp_name = file_name.to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    let mut tmp_file = fs::File::create(&tmp_path)?;
+    tmp_file.write_all(contents.as_bytes())?;
+    tmp_file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+/// The branch's most recent commit after skipping `client.commit_skip` newer
+/// ones. With one commit per page, page N+1 is exactly the commit to use.
+pub async fn fetch_recent_commit(client: &GitHubClient) -> Result<String> {
+    let url = format!(
+        "{}?sha={}&per_page=1&page={}",
+        client.repo.commits_url(),
+        percent_encode(client.branch.as_bytes(), NON_ALPHANUMERIC),
+        client.commit_skip + 1
+    );
+    let commits: Vec<CommitInfo> = check_status(send_with_retry(client, client.get(&url)).await?)
         .await?
         .json()
         .await?;
     if let Some(commit) = commits.first() {
-        info!(target:get_log_target(), "Fetched latest commit: {}", commit.sha);
+        info!(target:get_log_target(), commit = commit.sha.as_str(); "Fetched latest commit: {}", commit.sha);
         Ok(commit.sha.clone())
     } else {
-        error!(target:get_log_target(), "No commits found on build branch",);
-        Err("No commits found".into())
+        error!(target:get_log_target(), commit = log_commit();
+            "No commits found on {} branch (skip {})",
+            client.branch,
+            client.commit_skip
+        );
+        Err(WatchdogError::NoCommits {
+            branch: client.branch.clone(),
+            skip: client.commit_skip,
+        })
     }
 }
 use base64::{Engine as _, engine::general_purpose};
 pub async fn fetch_and_decode_file(
-    base_url: &str,
-    token: &str,
+    client: &GitHubClient,
     hash: &str,
-    status: &str,
+    status: FileStatus,
     base_commit: &str,
-) -> Result<Option<String>, Box<dyn std::error::Error>> {
-    let commit_ref = if status == "deleted" || status == "deleteduser" {
+) -> Result<Option<DecodedFile>> {
+    let commit_ref = if status == FileStatus::Deleted || status == FileStatus::DeletedUser {
         base_commit
     } else {
-        "build"
+        client.branch.as_str()
     };
-
-    let url = format!("{}/names/{}?ref={}", base_url, hash, commit_ref);
-    let client = reqwest::Client::new();
-    let file_resp = client
-        .get(&url)
-        .bearer_auth(token)
-        .header(USER_AGENT, "rust-webhook-server")
-        .header(ACCEPT, "application/vnd.github.v3+json")
-        .send()
-        .await?;
+    fetch_and_decode_file_at(client, hash, commit_ref).await
+}
+pub async fn fetch_and_decode_file_at(
+    client: &GitHubClient,
+    hash: &str,
+    commit_ref: &str,
+) -> Result<Option<DecodedFile>> {
+    fetch_and_decode_path(client, &names_path(hash)?, commit_ref).await
+}
+/// Names files list one username per line; blank lines and lines starting
+/// with `#` are ignored and surrounding whitespace is trimmed.
+pub fn parse_user_lines(raw: &str) -> Vec<String> {
+    raw.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+/// For identity changes (rename, delete) a names file must hold exactly one user.
+pub fn normalize_username(raw: &str) -> Result<String> {
+    match parse_user_lines(raw).as_slice() {
+        [username] if !username.contains(char::is_whitespace) => Ok(username.clone()),
+        _ => Err(WatchdogError::Decode(format!(
+            "Names file content {:?} is not a single username",
+            raw
+        ))),
+    }
+}
+pub async fn fetch_access_keys(
+    client: &GitHubClient,
+    provider: &str,
+    project: &str,
+    hash: &str,
+    commit_ref: &str,
+) -> Result<Option<String>> {
+    let path = access_path(provider, project, hash)?;
+    Ok(fetch_and_decode_path(client, &path, commit_ref)
+        .await?
+        .map(|decoded| decoded.content))
+}
+pub async fn fetch_and_decode_path(
+    client: &GitHubClient,
+    path: &str,
+    commit_ref: &str,
+) -> Result<Option<DecodedFile>> {
+    let url = format!(
+        "{}/{}?ref={}",
+        client.repo.contents_url(),
+        path,
+        percent_encode(commit_ref.as_bytes(), NON_ALPHANUMERIC)
+    );
+    let file_resp = send_with_ratelimit(client, client.get(&url)).await?;
     if !file_resp.status().is_success() {
-        warn!(target:get_log_target(),
-            "GitHub API returned error for file at hash {}: {}",
-            hash,
-            file_resp.status()
+        warn!(target:get_log_target(), commit = log_commit();
+            "GitHub API returned error for file {}: {}",
+            path,
+            error_from_response(file_resp).await
         );
         return Ok(None);
     }
-    let file_json = file_resp.json::<serde_json::Value>().await?;
-    if let Some(base64_content) = file_json["content"].as_str() {
+    let mut file = file_resp.json::<GitHubContent>().await?;
+    let content_is_empty = file.content.as_deref().is_none_or(str::is_empty);
+    if content_is_empty && let Some(git_url) = &file.git_url {
+        info!(target:get_log_target(), commit = log_commit();
+            "File {} has no inline content, fetching blob from {}",
+            path,
+            git_url
+        );
+        let blob_resp = send_with_ratelimit(client, client.get(git_url)).await?;
+        if !blob_resp.status().is_success() {
+            warn!(target:get_log_target(), commit = log_commit();
+                "GitHub API returned error for blob of {}: {}",
+                path,
+                error_from_response(blob_resp).await
+            );
+            return Ok(None);
+        }
+        file = blob_resp.json::<GitHubContent>().await?;
+    }
+    if let Some(base64_content) = &file.content {
         let clean_base64 = base64_content.replace('\n', "");
         let decoded = general_purpose::STANDARD.decode(&clean_base64)?;
-        let decoded_str = String::from_utf8(decoded)?;
-        info!(target:get_log_target(), "Decoded file for hash {}", hash);
-        Ok(Some(decoded_str))
+        let decoded_str = match String::from_utf8(decoded) {
+            Ok(decoded_str) => decoded_str,
+            Err(e) => {
+                warn!(target:get_log_target(), commit = log_commit(); "File {} is not valid UTF-8, skipping: {}", path, e);
+                return Ok(None);
+            }
+        };
+        info!(target:get_log_target(), commit = log_commit(); "Decoded file {} at blob {}", path, file.sha);
+        Ok(Some(DecodedFile {
+            content: decoded_str,
+            sha: file.sha,
+            size: file.size,
+            encoding: file.encoding,
+        }))
     } else {
-        warn!(target:get_log_target(), "No 'content' field found for file hash {}", hash);
+        warn!(target:get_log_target(), commit = log_commit(); "No 'content' field found for file {}", path);
         Ok(None)
     }
 }
-pub fn extract_diff_parts(diff_data: &str) -> Vec<(String, String, String, String)> {
-    let re_access = Regex::new(r"diff --git a/(access/([^/]+)/([^/]+)/([\w\d]+))").unwrap();
-    let re_names = Regex::new(r"diff --git a/(names/([\w\d]+))").unwrap();
-    let mut parts_with_status = HashMap::new();
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileChange {
+    Added,
+    Deleted,
+    Modified,
+    Renamed,
+}
+
+struct DiffFileBlock {
+    path: String,
+    change: FileChange,
+    rename_from: Option<String>,
+    rename_to: Option<String>,
+    in_header: bool,
+}
+
+impl DiffFileBlock {
+    fn new(paths: &str) -> Self {
+        let path = match paths.rfind(" b/") {
+            Some(idx) => &paths[..idx],
+            None => paths,
+        };
+        DiffFileBlock {
+            path: path.trim_start_matches("a/").to_string(),
+            change: FileChange::Modified,
+            rename_from: None,
+            rename_to: None,
+            in_header: true,
+        }
+    }
+
+    fn read_marker(&mut self, line: &str) {
+        if !self.in_header {
+            return;
+        }
+        if line.starts_with("new file mode") {
+            self.change = FileChange::Added;
+        } else if line.starts_with("deleted file mode") {
+            self.change = FileChange::Deleted;
+        } else if let Some(from) = line.strip_prefix("rename from ") {
+            self.rename_from = Some(from.to_string());
+        } else if let Some(to) = line.strip_prefix("rename to ") {
+            self.rename_to = Some(to.to_string());
+        } else if line.starts_with("--- ") || line.starts_with("@@") {
+            self.in_header = false;
+        }
+    }
+
+    fn into_change(self) -> (String, FileChange, Option<String>) {
+        match (self.rename_from, self.rename_to) {
+            (Some(from), Some(to)) => (to, FileChange::Renamed, Some(from)),
+            _ => (self.path, self.change, None),
+        }
+    }
+}
+
+fn diff_file_changes(diff_data: &str) -> Vec<(String, FileChange, Option<String>)> {
+    let mut changes = Vec::new();
+    let mut current: Option<DiffFileBlock> = None;
     for line in diff_data.lines() {
-        if let Some(caps) = re_access.captures(line) {
-            let full_path = &caps[1];
-            let project = &caps[2];
-            let provider = &caps[3];
-            let hash = &caps[4];
-            let status = if diff_data.contains("new file mode") && line.contains(full_path) {
-                "added"
-            } else if diff_data.contains("deleted file mode") && line.contains(full_path) {
-                "deleted"
+        if let Some(paths) = line.strip_prefix("diff --git ") {
+            if let Some(block) = current.take() {
+                changes.push(block.into_change());
+            }
+            current = Some(DiffFileBlock::new(paths));
+        } else if let Some(block) = current.as_mut() {
+            block.read_marker(line);
+        }
+    }
+    if let Some(block) = current {
+        changes.push(block.into_change());
+    }
+    changes
+}
+
+async fn list_repo_dir<T: DeserializeOwned>(
+    client: &GitHubClient,
+    segments: &[&str],
+) -> Result<Vec<T>> {
+    let url = format!(
+        "{}/{}?ref={}",
+        client.repo.contents_url(),
+        repo_path(segments)?,
+        percent_encode(client.branch.as_bytes(), NON_ALPHANUMERIC)
+    );
+    fetch_all_pages(client, &url).await
+}
+
+pub fn extract_diff_parts(diff_data: &str) -> Vec<DiffEntry> {
+    entries_from_changes(diff_file_changes(diff_data))
+}
+
+/// Parses the `files` array of a JSON compare response into diff entries.
+/// `removed` files revoke, `renamed` files inside one project refresh the
+/// grant in place, and files with an unknown status are skipped.
+pub fn entries_from_compare_json(compare: &Value) -> Vec<DiffEntry> {
+    entries_from_changes(compare_file_changes(compare))
+}
+
+fn compare_file_changes(compare: &Value) -> Vec<(String, FileChange, Option<String>)> {
+    let Some(files) = compare.get("files").and_then(Value::as_array) else {
+        warn!(target:get_log_target(), commit = log_commit(); "Compare response has no 'files' array");
+        return Vec::new();
+    };
+    files
+        .iter()
+        .filter_map(|file| {
+            let path = file.get("filename")?.as_str()?.to_string();
+            let status = file.get("status")?.as_str()?;
+            let change = match status {
+                "added" | "copied" => FileChange::Added,
+                "removed" => FileChange::Deleted,
+                "modified" | "changed" => FileChange::Modified,
+                "renamed" => match file.get("previous_filename").and_then(Value::as_str) {
+                    Some(from) => return Some((path, FileChange::Renamed, Some(from.to_string()))),
+                    None => FileChange::Added,
+                },
+                "unchanged" => return None,
+                other => {
+                    warn!(target:get_log_target(), commit = log_commit(); "Skipping {} with unknown compare status '{}'", path, other);
+                    return None;
+                }
+            };
+            Some((path, change, None))
+        })
+        .collect()
+}
+
+fn entries_from_changes(changes: Vec<(String, FileChange, Option<String>)>) -> Vec<DiffEntry> {
+    let classify = |path: &str, change: FileChange| -> Option<DiffEntry> {
+        if let Some(access) = parse_access_path(path) {
+            let status = match change {
+                _ if access.hash == PROJECT_META_FILE => FileStatus::ModifiedMeta,
+                FileChange::Added => FileStatus::Added,
+                FileChange::Deleted => FileStatus::Deleted,
+                FileChange::Modified | FileChange::Renamed => FileStatus::Modified,
+            };
+            Some(DiffEntry {
+                provider: access.provider,
+                project: access.project,
+                hash: access.hash,
+                status,
+                old_hash: None,
+            })
+        } else if let Some(hash) = parse_names_path(path) {
+            let status = if change == FileChange::Deleted {
+                FileStatus::DeletedUser
             } else {
-                "modified"
+                FileStatus::ModifiedUser
             };
-            info!(target:get_log_target(),
-                "Access file change detected: {}/{}/{}, status: {}",
-                project, provider, hash, status
-            );
-            parts_with_status
-                .entry((project.to_string(), provider.to_string(), hash.to_string()))
-                .or_insert(status.to_string());
-        } else if let Some(caps) = re_names.captures(line) {
-            let full_path = &caps[1];
-            let hash = &caps[2];
-            let status = if diff_data.contains("deleted file mode") && line.contains(full_path) {
-                "deleteduser"
+            Some(DiffEntry {
+                provider: "".to_string(),
+                project: "names".to_string(),
+                hash,
+                status,
+                old_hash: None,
+            })
+        } else {
+            None
+        }
+    };
+    let mut parts_with_status: Vec<DiffEntry> = Vec::new();
+    for (path, change, renamed_from) in changes {
+        let parts = match renamed_from {
+            // A rename inside one access project is a key rotation: refresh the
+            // grant in place instead of revoking it and granting it again.
+            Some(from) => match (parse_access_path(&from), parse_access_path(&path)) {
+                (Some(old), Some(new))
+                    if old.provider == new.provider && old.project == new.project =>
+                {
+                    vec![DiffEntry {
+                        provider: new.provider,
+                        project: new.project,
+                        hash: new.hash,
+                        status: FileStatus::Renamed,
+                        old_hash: Some(old.hash),
+                    }]
+                }
+                _ => classify(&from, FileChange::Deleted)
+                    .into_iter()
+                    .chain(classify(&path, FileChange::Added))
+                    .collect(),
+            },
+            None => classify(&path, change).into_iter().collect(),
+        };
+        for part in parts {
+            if part.project == "names" {
+                info!(target:get_log_target(), commit = log_commit(); "Name file change detected: {}, status: {}", part.hash, part.status);
             } else {
-                "modifieduser"
+                info!(target:get_log_target(), commit = log_commit();
+                    "Access file change detected: {}/{}/{}, status: {}",
+                    part.provider, part.project, part.hash, part.status
+                );
+            }
+            let existing = parts_with_status.iter().position(|existing| {
+                (&existing.provider, &existing.project, &existing.hash)
+                    == (&part.provider, &part.project, &part.hash)
+            });
+            let Some(index) = existing else {
+                parts_with_status.push(part);
+                continue;
             };
-            info!(target:get_log_target(), "Name file change detected: {}, status: {}", hash, status);
-            parts_with_status
-                .entry(("".to_string(), "names".to_string(), hash.to_string()))
-                .or_insert(status.to_string());
+            match merge_status(parts_with_status[index].status, part.status) {
+                Some(status) => {
+                    let existing = &mut parts_with_status[index];
+                    existing.status = status;
+                    if existing.old_hash.is_none() {
+                        existing.old_hash = part.old_hash;
+                    }
+                }
+                None => {
+                    info!(target:get_log_target(), commit = log_commit(); "File {} added and deleted in range, skipping", part.hash);
+                    parts_with_status.remove(index);
+                }
+            }
         }
     }
     parts_with_status
-        .into_iter()
-        .map(|((proj, prov, hash), status)| (proj, prov, hash, status))
-        .collect()
 }
-pub async fn fetch_diff(
-    base_url: &str,
+// The same file reported twice in one compare window. The result depends only
+// on the two statuses, never on which block or line carried them: an add that
+// is later deleted never needs applying (None), a delete followed by a re-add
+// is just a refresh, and otherwise the later change refines the earlier one.
+fn merge_status(earlier: FileStatus, later: FileStatus) -> Option<FileStatus> {
+    match (earlier, later) {
+        (FileStatus::Added, FileStatus::Deleted) => None,
+        (FileStatus::Added, FileStatus::Modified | FileStatus::Renamed) => Some(FileStatus::Added),
+        (FileStatus::Deleted, FileStatus::Added | FileStatus::Modified | FileStatus::Renamed) => {
+            Some(FileStatus::Modified)
+        }
+        (FileStatus::DeletedUser, FileStatus::ModifiedUser) => Some(FileStatus::ModifiedUser),
+        (_, later) => Some(later),
+    }
+}
+pub fn is_valid_sha(s: &str) -> bool {
+    (s.len() == 40 || s.len() == 64) && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+/// Fetches the compare between two commits in the client's `compare_format`
+/// and parses it into diff entries.
+pub async fn fetch_diff_entries(
+    client: &GitHubClient,
     base: &str,
     merge: &str,
-    token: &str,
-) -> Result<String, Box<dyn std::error::Error>> {
-    let client = reqwest::Client::new();
-    let clean_base: &str = base_url.trim_end_matches("/contents");
-    let url = format!("{}/compare/{}...{}", clean_base, base, merge);
-
-    info!(target:get_log_target(), "Fetching diff from GitHub: {}", url);
-    let response = client
-        .get(&url)
-        .header(USER_AGENT, "rust-webhook-server")
-        .header(ACCEPT, "application/vnd.github.v3.diff")
-        .bearer_auth(token)
-        .send()
+) -> Result<Vec<DiffEntry>> {
+    match client.compare_format {
+        CompareFormat::Diff => Ok(extract_diff_parts(&fetch_diff(client, base, merge).await?)),
+        CompareFormat::Json => Ok(entries_from_compare_json(
+            &fetch_compare_json(client, base, merge).await?,
+        )),
+    }
+}
+
+fn compare_url(client: &GitHubClient, base: &str, merge: &str) -> Result<String> {
+    for sha in [base, merge] {
+        if !is_valid_sha(sha) {
+            return Err(WatchdogError::GitHub(format!(
+                "Refusing to compare invalid commit SHA {:?}",
+                sha
+            )));
+        }
+    }
+    Ok(client.repo.compare_url(base, merge))
+}
+
+pub async fn fetch_compare_json(client: &GitHubClient, base: &str, merge: &str) -> Result<Value> {
+    let url = compare_url(client, base, merge)?;
+
+    info!(target:get_log_target(), commit = log_commit(); "Fetching compare from GitHub: {}", url);
+    // Large compares paginate `files`; every page repeats the commit metadata,
+    // so keep the first page and append the file lists of the rest.
+    let mut compare: Option<Value> = None;
+    let mut next_url = Some(format!("{}?per_page=100", url));
+    while let Some(page_url) = next_url {
+        let response = send_with_retry(
+            client,
+            client
+                .get(&page_url)
+                .header(ACCEPT, "application/vnd.github+json"),
+        )
         .await?;
+        let response = check_compare_status(response, base).await?;
+        next_url = next_page_url(response.headers());
+        let page = response.json::<Value>().await?;
+        match compare.as_mut() {
+            None => compare = Some(page),
+            Some(compare) => {
+                let files = page
+                    .get("files")
+                    .and_then(Value::as_array)
+                    .cloned()
+                    .unwrap_or_default();
+                if let Some(all_files) = compare.get_mut("files").and_then(Value::as_array_mut) {
+                    all_files.extend(files);
+                }
+            }
+        }
+    }
+    info!(target:get_log_target(), commit = log_commit(); "Fetched compare between {} and {}", base, merge);
+    Ok(compare.unwrap_or(Value::Null))
+}
 
-    let diff = response.text().await?;
-    info!(target:get_log_target(), "Fetched diff between {} and {}", base, merge);
-    Ok(diff)
+// GitHub answers 422 ("No common ancestor") or 404 when the base commit no
+// longer exists in the repository, e.g. after a force-push.
+async fn check_compare_status(response: Response, base: &str) -> Result<Response> {
+    let status = response.status();
+    if status == StatusCode::UNPROCESSABLE_ENTITY || status == StatusCode::NOT_FOUND {
+        let error = error_from_response(response).await;
+        debug!(target:get_log_target(), commit = log_commit(); "Compare from {} failed: {}", base, error);
+        return Err(WatchdogError::UnreachableBase {
+            base: base.to_string(),
+            status: status.as_u16(),
+        });
+    }
+    check_status(response).await
 }
 
-pub async fn update_all_users(
-    base_url: &str,
-    token: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let client = reqwest::Client::new();
+pub async fn fetch_diff(client: &GitHubClient, base: &str, merge: &str) -> Result<String> {
+    let url = compare_url(client, base, merge)?;
 
-    let url = format!("{}/access?ref=build", base_url);
+    info!(target:get_log_target(), commit = log_commit(); "Fetching diff from GitHub: {}", url);
+    let response = send_with_retry(
+        client,
+        client
+            .get(&url)
+            .header(ACCEPT, "application/vnd.github.v3.diff"),
+    )
+    .await?;
 
-    let providers_resp = client
-        .get(&url)
-        .bearer_auth(token)
-        .header(USER_AGENT, "rust-webhook-server")
-        .header(ACCEPT, "application/vnd.github.v3+json")
-        .send()
-        .await?;
+    let diff = check_compare_status(response, base).await?.text().await?;
+    info!(target:get_log_target(), commit = log_commit(); "Fetched diff between {} and {}", base, merge);
+    Ok(diff)
+}
 
-    let providers: Vec<Value> = providers_resp.json().await?;
-    let mut cloud_providers = vec![];
+pub async fn update_all_users(client: &GitHubClient, hostname: &str) -> Result<SyncReport> {
+    Ok(full_sync(client, hostname).await?.report)
+}
 
-    for provider in &providers {
-        if let Some(name) = provider["name"].as_str() {
-            cloud_providers.push(name.to_string());
-        }
-    }
+/// A file found by a full sync's directory listings, with its blob SHA.
+struct ListedFile {
+    provider: String,
+    project: String,
+    name: String,
+    sha: String,
+}
 
-    for provider in cloud_providers {
-        let provider_url = format!("{}/access/{}?ref=build", base_url, provider);
+/// What a full sync reads from the repo for this host, before applying it.
+struct RepoGrants {
+    /// User -> projects (groups) granted.
+    memberships: BTreeMap<String, Vec<String>>,
+    /// (user, keys source, hash, keys) for single-user access files.
+    pending_keys: Vec<(String, String, String, Option<String>)>,
+    project_metas: BTreeMap<String, ProjectMeta>,
+    /// Listings and files that could not be read, i.e. grants possibly missed.
+    errors: Vec<String>,
+}
 
-        let projects_resp = client
-            .get(&provider_url)
-            .bearer_auth(token)
-            .header(USER_AGENT, "rust-webhook-server")
-            .header(ACCEPT, "application/vnd.github.v3+json")
-            .send()
-            .await?;
+struct FullSyncOutcome {
+    report: SyncReport,
+    /// Every user the repo grants on this host, which is what orphan pruning
+    /// compares the managed homes against.
+    granted: BTreeSet<String>,
+    /// Listings and files that could not be read, i.e. grants possibly missed.
+    read_failures: usize,
+}
 
-        let projects: Vec<Value> = projects_resp.json().await?;
+async fn full_sync(client: &GitHubClient, hostname: &str) -> Result<FullSyncOutcome> {
+    let mut grants = read_grants(client, hostname).await?;
+    let read_failures = grants.errors.len();
+    let mut report = SyncReport {
+        errors: std::mem::take(&mut grants.errors),
+        ..SyncReport::default()
+    };
+    let granted = grants.memberships.keys().cloned().collect();
+    run_user_commands(&mut report, move |report| apply_grants(report, grants)).await;
 
-        for project in &projects {
-            if let Some(project_name) = project["name"].as_str() {
-                let url = format!(
-                    "{}/access/{}/{}?ref=build",
-                    base_url, provider, project_name
-                );
+    Ok(FullSyncOutcome {
+        report,
+        granted,
+        read_failures,
+    })
+}
 
-                let response = client
-                    .get(&url)
-                    .bearer_auth(token)
-                    .header(ACCEPT, "application/vnd.github.v3+json")
-                    .header(USER_AGENT, "rust-webhook-server")
-                    .send()
-                    .await?;
+// The user-command half of a full sync: memberships, project settings and keys.
+fn apply_grants(report: &mut SyncReport, grants: RepoGrants) {
+    for (user, groups) in &grants.memberships {
+        let mut groups = groups.clone();
+        let metas: Vec<&ProjectMeta> = groups
+            .iter()
+            .filter_map(|group| grants.project_metas.get(group))
+            .collect();
+        let shell = metas.iter().find_map(|meta| meta.shell.clone());
+        if metas.iter().any(|meta| meta.grant_sudo) && !groups.iter().any(|g| g == "sudo") {
+            groups.push("sudo".to_string());
+        }
+        add_groups_and_record(report, user, &groups);
+        if let Some(shell) = shell {
+            set_shell_and_record(report, user, &shell);
+        }
+    }
+    for (user, source, hash, keys) in grants.pending_keys {
+        record_access_keys(report, &user, &source, &hash, keys);
+    }
+}
 
-                if response.status().is_success() {
-                    let files: Vec<GitHubContent> = response.json().await?;
+async fn read_grants(client: &GitHubClient, hostname: &str) -> Result<RepoGrants> {
+    let mut report = SyncReport::default();
+    let providers: Vec<GitHubContent> = list_repo_dir(client, &[ACCESS_DIR]).await?;
+    let mut cloud_providers = vec![];
 
-                    for file in files {
-                        let hash = &file.name;
+    for provider in providers.iter().filter(|entry| entry.is_dir()) {
+        if !client.handles_provider(&provider.name, hostname) {
+            info!(target:get_log_target(), commit = log_commit(); "Skipping provider {}, not this server", provider.name);
+            continue;
+        }
+        cloud_providers.push(provider.name.clone());
+    }
 
-                        if let Some(decoded_str) =
-                            fetch_and_decode_file(base_url, token, hash, "added", "").await?
-                        {
-                            info!(target:get_log_target(),
-                                "Adding user to group for project {}: {}",
-                                project_name, decoded_str
+    let mut access_files = vec![];
+    let mut meta_files = vec![];
+    for provider in cloud_providers {
+        let projects: Vec<GitHubContent> = match list_repo_dir(client, &[ACCESS_DIR, &provider])
+            .await
+        {
+            Ok(projects) => projects,
+            Err(e) => {
+                error!(target:get_log_target(), commit = log_commit(); "Failed to list projects for provider {}: {}", provider, e);
+                report.errors.push(format!(
+                    "Failed to list projects for provider {}: {}",
+                    provider, e
+                ));
+                continue;
+            }
+        };
+
+        for project in projects.iter().filter(|entry| entry.is_dir()) {
+            let project_name = project.name.as_str();
+            match list_repo_dir::<GitHubContent>(client, &[ACCESS_DIR, &provider, project_name])
+                .await
+            {
+                Ok(entries) => {
+                    for entry in entries {
+                        if entry.is_file() {
+                            let file = ListedFile {
+                                provider: provider.clone(),
+                                project: project_name.to_string(),
+                                name: entry.name,
+                                sha: entry.sha,
+                            };
+                            if file.name == PROJECT_META_FILE {
+                                meta_files.push(file);
+                            } else {
+                                access_files.push(file);
+                            }
+                        } else if entry.is_dir() {
+                            warn!(target:get_log_target(), commit = log_commit();
+                                "Ignoring unexpected directory {} in project {}",
+                                entry.path,
+                                project_name
                             );
-                            add_user_to_group(&decoded_str, project_name).unwrap_or_else(|e| {
-                                error!(target:get_log_target(), "Failed to add user in update_all_users: {}", e);
-                            });
+                        } else {
+                            debug!(target:get_log_target(), commit = log_commit(); "Ignoring {} entry {}", entry.content_type, entry.path);
                         }
                     }
-                } else {
-                    error!(target:get_log_target(),
-                        "Failed to fetch content for project {}. Status: {}",
+                }
+                Err(e) => {
+                    error!(target:get_log_target(), commit = log_commit();
+                        "Failed to fetch content for project {}: {}",
                         project_name,
-                        response.status()
+                        e
                     );
+                    report.errors.push(format!(
+                        "Failed to fetch content for project {}: {}",
+                        project_name, e
+                    ));
                 }
             }
         }
     }
 
-    Ok(())
+    let names_shas = list_names_shas(client).await;
+    let names_shas = &names_shas;
+    let fetched: Vec<_> = stream::iter(access_files)
+        .map(|access| async move {
+            let names_sha = names_shas.get(&access.name).map(String::as_str);
+            let result = fetch_full_sync_file(client, &access, names_sha)
+                .await
+                .map_err(|e| e.to_string());
+            (access.provider, access.project, access.name, result)
+        })
+        .buffer_unordered(client.fetch_concurrency.max(1))
+        .collect()
+        .await;
+
+    let project_metas = fetch_project_metas(client, &mut report, meta_files).await;
+    let mut memberships: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut pending_keys = Vec::new();
+    for (provider, project_name, hash, result) in fetched {
+        match result {
+            Ok(Some((users, keys))) => {
+                for user in &users {
+                    info!(target:get_log_target(), commit = log_commit();
+                        "Queueing group {} for user {}",
+                        project_name, user
+                    );
+                    memberships
+                        .entry(user.clone())
+                        .or_default()
+                        .push(project_name.clone());
+                }
+                match users.as_slice() {
+                    [user] => pending_keys.push((
+                        user.clone(),
+                        keys_source(&provider, &project_name),
+                        hash,
+                        keys,
+                    )),
+                    _ => {
+                        info!(target:get_log_target(), commit = log_commit(); "Access file {} lists {} users, not installing shared keys", hash, users.len());
+                    }
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!(target:get_log_target(), commit = log_commit(); "Failed to process file {} for project {}: {}", hash, project_name, e);
+                report.errors.push(format!(
+                    "Failed to process file {} for project {}: {}",
+                    hash, project_name, e
+                ));
+            }
+        }
+    }
+
+    Ok(RepoGrants {
+        memberships,
+        pending_keys,
+        project_metas,
+        errors: report.errors,
+    })
+}
+
+// Users the other configured repos grant on this host. They share the home
+// base with this repo's users, so pruning has to keep them too.
+async fn sibling_granted(
+    keyhouse_config: &KeyhouseConf,
+    hostname: &str,
+) -> Result<BTreeSet<String>> {
+    let mut granted = BTreeSet::new();
+    for sibling in &keyhouse_config.sibling_repos {
+        let client = GitHubClient::new(sibling)?;
+        client.refresh_auth().await?;
+        let grants = read_grants(&client, hostname).await?;
+        if let Some(e) = grants.errors.first() {
+            return Err(WatchdogError::GitHub(format!(
+                "Could not read every grant of {}: {}",
+                sibling.base_url, e
+            )));
+        }
+        granted.extend(grants.memberships.into_keys());
+    }
+    Ok(granted)
 }
 
-pub async fn fetch_latest_commit(base_url: &str, token: &str) -> Result<String> {
-    let clean_base: &str = base_url.trim_end_matches("/contents");
-    let url = format!("{}/commits/build", clean_base);
+pub fn find_orphans(managed: &[String], seen: &BTreeSet<String>) -> Vec<String> {
+    managed
+        .iter()
+        .filter(|user| !seen.contains(*user))
+        .cloned()
+        .collect()
+}
 
-    let client = Client::new();
-    let response = client
-        .get(&url)
-        .header("Authorization", format!("token {}", token))
-        .header("User-Agent", "scout-bot")
-        .send()
-        .await?;
+fn prune_orphans(report: &mut SyncReport, seen: &BTreeSet<String>, max_deletions: usize) {
+    let managed = match list_managed_users().map_err(user_command_error) {
+        Ok(managed) => managed,
+        Err(e) => {
+            error!(target:get_log_target(), commit = log_commit(); "Failed to list managed users for pruning: {}", e);
+            report
+                .errors
+                .push(format!("Failed to list managed users for pruning: {}", e));
+            return;
+        }
+    };
+    let mut orphans = find_orphans(&managed, seen);
+    // Disabled users are already on their way out; disabling them again would
+    // be pointless, and purge_disabled_users removes them after retention.
+    if get_user_conf().deletion_policy == DeletionPolicy::Disable {
+        orphans.retain(|user| !is_disabled(user).unwrap_or(false));
+    }
+    if orphans.is_empty() {
+        return;
+    }
+    info!(target:get_log_target(), commit = log_commit(); "Orphaned managed users: {}", orphans.join(", "));
+    if orphans.len() > max_deletions {
+        error!(target:get_log_target(), commit = log_commit();
+            "Refusing to prune {} orphans (max_deletions = {})",
+            orphans.len(),
+            max_deletions
+        );
+        report.errors.push(
+            WatchdogError::TooManyDeletions {
+                count: orphans.len(),
+                limit: max_deletions,
+            }
+            .to_string(),
+        );
+        return;
+    }
+    for user in orphans {
+        report.changed = true;
+        match delete_user(&user).map_err(user_command_error) {
+            Ok(()) => report.users_deleted += 1,
+            Err(e) => {
+                error!(target:get_log_target(), commit = log_commit(); "Failed to prune orphan '{}': {}", user, e);
+                report
+                    .errors
+                    .push(format!("Failed to prune orphan '{}': {}", user, e));
+            }
+        }
+    }
+}
+
+// One usermod per user; if the batch fails, retry each group on its own so
+// the failing group is isolated in the report.
+fn add_groups_and_record(report: &mut SyncReport, user: &str, groups: &[String]) {
+    let existed = user_exists(user).unwrap_or(true);
+    match add_user_to_groups(user, groups).map_err(user_command_error) {
+        Ok(outcomes) => {
+            report.groups_added += outcomes.iter().filter(|o| o.group_added()).count();
+            if outcomes.iter().any(GroupAddOutcome::user_created) {
+                report.users_created += 1;
+            }
+            if outcomes.iter().any(|o| o.group_added() || o.user_created()) {
+                report.changed = true;
+            }
+        }
+        Err(e) => {
+            warn!(target:get_log_target(), commit = log_commit();
+                "Batch group add for '{}' failed ({}), retrying groups individually",
+                user,
+                e
+            );
+            if !existed && user_exists(user).unwrap_or(false) {
+                report.users_created += 1;
+                report.changed = true;
+            }
+            for group in groups {
+                add_and_record(
+                    report,
+                    user,
+                    group,
+                    "Failed to add user in update_all_users",
+                );
+            }
+        }
+    }
+}
+
+// Keyed by project name, like the groups themselves. A meta file that cannot
+// be fetched or parsed is reported and the project is provisioned without it.
+async fn fetch_project_metas(
+    client: &GitHubClient,
+    report: &mut SyncReport,
+    meta_files: Vec<ListedFile>,
+) -> BTreeMap<String, ProjectMeta> {
+    let mut metas = BTreeMap::new();
+    for file in meta_files {
+        if let Some(meta) = fetch_project_meta(
+            client,
+            report,
+            &file.provider,
+            &file.project,
+            Some(&file.sha),
+        )
+        .await
+        {
+            metas.insert(file.project, meta);
+        }
+    }
+    metas
+}
+
+async fn fetch_project_meta(
+    client: &GitHubClient,
+    report: &mut SyncReport,
+    provider: &str,
+    project: &str,
+    listed_sha: Option<&str>,
+) -> Option<ProjectMeta> {
+    let fetched = match project_meta_path(provider, project) {
+        Ok(path) => fetch_cached_path(client, &path, listed_sha).await,
+        Err(e) => Err(e),
+    };
+    let parsed = match fetched {
+        Ok(Some(raw)) => parse_project_meta(&raw),
+        Ok(None) => return None,
+        Err(e) => Err(e),
+    };
+    match parsed {
+        Ok(meta) => {
+            debug!(target:get_log_target(), commit = log_commit(); "Project {} metadata: {:?}", project, meta);
+            Some(meta)
+        }
+        Err(e) => {
+            error!(target:get_log_target(), commit = log_commit(); "Failed to load metadata for project {}: {}", project, e);
+            report.errors.push(format!(
+                "Failed to load metadata for project {}: {}",
+                project, e
+            ));
+            None
+        }
+    }
+}
+
+// Like a full sync, metadata only ever adds: dropping grant_sudo or the shell
+// leaves users as they are.
+async fn apply_project_meta_blocking(
+    report: &mut SyncReport,
+    users: Vec<String>,
+    meta: ProjectMeta,
+) {
+    run_user_commands(report, move |report| {
+        apply_project_meta(report, &users, &meta)
+    })
+    .await;
+}
+fn apply_project_meta(report: &mut SyncReport, users: &[String], meta: &ProjectMeta) {
+    for user in users {
+        if meta.grant_sudo {
+            add_and_record(
+                report,
+                user,
+                "sudo",
+                "Failed to grant sudo from project metadata",
+            );
+        }
+        if let Some(shell) = &meta.shell {
+            set_shell_and_record(report, user, shell);
+        }
+    }
+}
 
-    if !response.status().is_success() {
-        return Err(anyhow!(
-            "Failed to fetch latest commit. Status: {}",
-            response.status()
+// A changed meta.json is applied to everyone the project currently grants.
+async fn apply_project_meta_change(
+    client: &GitHubClient,
+    report: &mut SyncReport,
+    provider: &str,
+    project: &str,
+) -> Result<()> {
+    let Some(meta) = fetch_project_meta(client, report, provider, project, None).await else {
+        info!(target:get_log_target(), commit = log_commit(); "Project {} has no metadata, nothing to apply", project);
+        return Ok(());
+    };
+    let entries: Vec<GitHubContent> =
+        list_repo_dir(client, &[ACCESS_DIR, provider, project]).await?;
+    for entry in entries
+        .iter()
+        .filter(|entry| entry.is_file() && entry.name != PROJECT_META_FILE)
+    {
+        if let Some(decoded) = fetch_and_decode_file_at(client, &entry.name, &client.branch).await?
+        {
+            apply_project_meta_blocking(report, parse_user_lines(&decoded.content), meta.clone())
+                .await;
+        }
+    }
+    Ok(())
+}
+
+pub fn parse_project_meta(raw: &str) -> Result<ProjectMeta> {
+    let meta: ProjectMeta = serde_json::from_str(raw)?;
+    if let Some(shell) = &meta.shell
+        && !shell.starts_with('/')
+    {
+        return Err(WatchdogError::Decode(format!(
+            "Project shell must be an absolute path, got {:?}",
+            shell
+        )));
+    }
+    Ok(meta)
+}
+
+fn set_shell_and_record(report: &mut SyncReport, user: &str, shell: &str) {
+    if let Err(e) = set_login_shell(user, shell).map_err(user_command_error) {
+        error!(target:get_log_target(), commit = log_commit(); "Failed to set shell for '{}': {}", user, e);
+        report.errors.push(format!(
+            "Failed to set shell of '{}' to '{}': {}",
+            user, shell, e
         ));
     }
+}
+
+// Blob SHAs of the names files, for cache lookups. Without a cache, or if the
+// listing fails, every names file is simply fetched.
+async fn list_names_shas(client: &GitHubClient) -> BTreeMap<String, String> {
+    if client.cache_dir.is_none() {
+        return BTreeMap::new();
+    }
+    match list_repo_dir::<GitHubContent>(client, &[NAMES_DIR]).await {
+        Ok(entries) => entries
+            .into_iter()
+            .filter(|entry| entry.is_file())
+            .map(|entry| (entry.name, entry.sha))
+            .collect(),
+        Err(e) => {
+            warn!(target:get_log_target(), commit = log_commit(); "Failed to list names for the content cache: {}", e);
+            BTreeMap::new()
+        }
+    }
+}
+
+async fn fetch_full_sync_file(
+    client: &GitHubClient,
+    access: &ListedFile,
+    names_sha: Option<&str>,
+) -> Result<Option<(Vec<String>, Option<String>)>> {
+    let Some(decoded_str) =
+        fetch_cached_path(client, &names_path(&access.name)?, names_sha).await?
+    else {
+        return Ok(None);
+    };
+    let users = parse_user_lines(&decoded_str);
+    let keys = fetch_cached_path(
+        client,
+        &access_path(&access.provider, &access.project, &access.name)?,
+        Some(&access.sha),
+    )
+    .await?;
+    Ok(Some((users, keys)))
+}
+
+// `listed_sha` is the blob SHA a directory listing reported for `path`. What
+// gets cached is keyed by the SHA GitHub returns with the content, so a file
+// that changed between listing and fetch is never stored under the old SHA.
+async fn fetch_cached_path(
+    client: &GitHubClient,
+    path: &str,
+    listed_sha: Option<&str>,
+) -> Result<Option<String>> {
+    if let (Some(cache_dir), Some(sha)) = (&client.cache_dir, listed_sha)
+        && let Some(cached) = content_cache::load(cache_dir, sha)
+    {
+        debug!(target:get_log_target(), commit = log_commit(); "Using cached content for {} (blob {})", path, sha);
+        return Ok(Some(cached));
+    }
+    let Some(decoded) = fetch_and_decode_path(client, path, &client.branch).await? else {
+        return Ok(None);
+    };
+    if let Some(cache_dir) = &client.cache_dir
+        && let Err(e) = content_cache::store(cache_dir, &decoded.sha, &decoded.content)
+    {
+        warn!(target:get_log_target(), commit = log_commit(); "Failed to cache content for {}: {}", path, e);
+    }
+    Ok(Some(decoded.content))
+}
+
+/// Resolves the commit a full sync records as its pointer. The branch endpoint
+/// is tried first (with transient retries); if it still fails the commits
+/// listing is used instead, so a flaky endpoint doesn't force another full
+/// sync on the next run.
+pub async fn fetch_pointer_commit(client: &GitHubClient) -> Result<String> {
+    if client.commit_skip > 0 {
+        return fetch_recent_commit(client).await;
+    }
+    match fetch_latest_commit(client).await {
+        Ok(sha) => Ok(sha),
+        Err(e) => {
+            warn!(target:get_log_target(), commit = log_commit();
+                "Failed to fetch latest commit of {} ({}), falling back to the commits listing",
+                client.branch,
+                e
+            );
+            fetch_recent_commit(client).await
+        }
+    }
+}
+pub async fn fetch_latest_commit(client: &GitHubClient) -> Result<String> {
+    let url = format!(
+        "{}/{}",
+        client.repo.commits_url(),
+        percent_encode(client.branch.as_bytes(), NON_ALPHANUMERIC)
+    );
+
+    let response = check_status(send_with_retry(client, client.get(&url)).await?).await?;
 
     let json: Value = response.json().await?;
     if let Some(sha) = json["sha"].as_str() {
         Ok(sha.to_string())
     } else {
-        Err(anyhow!("SHA not found in commit response"))
+        Err(WatchdogError::GitHub(
+            "SHA not found in commit response".to_string(),
+        ))
     }
 }
+
+#[cfg(test)]
+mod tests;
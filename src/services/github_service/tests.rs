@@ -0,0 +1,1353 @@
+// A fake GitHub (wiremock) serving canned commits, compares and contents, with
+// user commands recorded by a RecordingRunner instead of being run.
+// Each #[tokio::test] has its own single-threaded runtime, so holding the
+// global test lock across awaits can't deadlock.
+#![allow(clippy::await_holding_lock)]
+
+use super::*;
+use crate::config::{COMMIT_FILE_NAME, UserConf};
+use crate::services::commit_store::MemoryCommitStore;
+use crate::test_support::{RecordingRunner, global_lock, install, output};
+use base64::engine::general_purpose::STANDARD;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use tempfile::TempDir;
+use wiremock::matchers::{method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const BASE: &str = "1111111111111111111111111111111111111111";
+const HEAD: &str = "2222222222222222222222222222222222222222";
+const HOST: &str = "host-1";
+const REPO: &str = "/repos/octo/keyhouse";
+
+// A mock server plus a scratch state_dir, so status files and the content
+// cache never land in the working directory.
+struct FakeGitHub {
+    server: MockServer,
+    state_dir: TempDir,
+}
+
+impl FakeGitHub {
+    async fn start() -> Self {
+        FakeGitHub {
+            server: MockServer::start().await,
+            state_dir: TempDir::new().expect("state dir"),
+        }
+    }
+
+    fn config(&self, extra: &str) -> KeyhouseConf {
+        toml::from_str(&format!(
+            "base_url = \"{}{}\"\ntoken = \"test-token\"\nbranch = \"main\"\nstate_dir = {:?}\n\
+             auto_create_groups = true\nretry_base_delay_ms = 1\n\
+             compare_format = \"json\"\n{}",
+            self.server.uri(),
+            REPO,
+            self.state_dir.path(),
+            extra
+        ))
+        .expect("test config")
+    }
+
+    async fn head(&self, sha: &str) {
+        Mock::given(method("GET"))
+            .and(path(format!("{}/commits", REPO)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([{ "sha": sha }])))
+            .mount(&self.server)
+            .await;
+    }
+
+    async fn compare(&self, files: Value) {
+        Mock::given(method("GET"))
+            .and(path(format!("{}/compare/{}...{}", REPO, BASE, HEAD)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "files": files })))
+            .mount(&self.server)
+            .await;
+    }
+
+    async fn file_at(&self, repo_path: &str, commit_ref: &str, content: &str) {
+        let name = repo_path.rsplit('/').next().unwrap_or(repo_path);
+        Mock::given(method("GET"))
+            .and(path(format!("{}/contents/{}", REPO, repo_path)))
+            .and(query_param("ref", commit_ref))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "name": name,
+                "path": repo_path,
+                "type": "file",
+                "sha": blob_sha(content),
+                "size": content.len(),
+                "encoding": "base64",
+                "content": STANDARD.encode(content),
+            })))
+            .mount(&self.server)
+            .await;
+    }
+
+    async fn file(&self, repo_path: &str, content: &str) {
+        self.file_at(repo_path, "main", content).await;
+    }
+
+    async fn dir(&self, repo_path: &str, entries: &[(&str, &str)]) {
+        let listing: Vec<Value> = entries
+            .iter()
+            .map(|(name, kind)| {
+                json!({
+                    "name": name,
+                    "path": format!("{}/{}", repo_path, name),
+                    "type": kind,
+                    "sha": blob_sha(name),
+                })
+            })
+            .collect();
+        Mock::given(method("GET"))
+            .and(path(format!("{}/contents/{}", REPO, repo_path)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(listing))
+            .mount(&self.server)
+            .await;
+    }
+}
+
+fn blob_sha(content: &str) -> String {
+    hex::encode(Sha256::digest(content.as_bytes()))[..40].to_string()
+}
+
+async fn run_sync(
+    keyhouse_config: KeyhouseConf,
+    store: &MemoryCommitStore,
+    runner: &Arc<RecordingRunner>,
+) -> SyncReport {
+    install(runner, (&keyhouse_config).into());
+    process_update_request(keyhouse_config, "watchdog-test", HOST.to_string(), store)
+        .await
+        .expect("sync")
+}
+
+#[tokio::test]
+async fn incremental_add_creates_user_joins_group_and_advances_pointer() {
+    let _guard = global_lock();
+    let github = FakeGitHub::start().await;
+    github.head(HEAD).await;
+    github
+        .compare(json!([{ "filename": "access/host-1/proj/abc123", "status": "added" }]))
+        .await;
+    github.file("names/abc123", "alice\n").await;
+    github
+        .file("access/host-1/proj/abc123", "ssh-ed25519 AAAAkey alice\n")
+        .await;
+    let store = MemoryCommitStore::new(Some(BASE.to_string()));
+    let runner = RecordingRunner::new();
+
+    let report = run_sync(github.config(""), &store, &runner).await;
+
+    assert!(report.errors.is_empty(), "{:?}", report.errors);
+    assert!(runner.ran_starting_with("sudo useradd -m -d /opt/watchdog/users/alice"));
+    assert!(runner.ran("sudo groupadd proj"));
+    assert!(runner.ran("sudo usermod -aG proj alice"));
+    assert!(
+        runner
+            .commands()
+            .iter()
+            .any(|run| run.starts_with("sudo -u alice -- sh -c") && run.contains("AAAAkey"))
+    );
+    assert_eq!(report.users_created, 1);
+    assert_eq!(report.groups_added, 1);
+    assert_eq!(store.load().unwrap().as_deref(), Some(HEAD));
+    assert_eq!(report.processed_commit, HEAD);
+}
+
+#[tokio::test]
+async fn incremental_sync_skips_other_hosts_files() {
+    let _guard = global_lock();
+    let github = FakeGitHub::start().await;
+    github.head(HEAD).await;
+    github
+        .compare(json!([{ "filename": "access/host-2/proj/abc123", "status": "added" }]))
+        .await;
+    let store = MemoryCommitStore::new(Some(BASE.to_string()));
+    let runner = RecordingRunner::new();
+
+    let report = run_sync(github.config(""), &store, &runner).await;
+
+    assert_eq!(report.skipped_other_host, 1);
+    assert!(runner.commands().is_empty(), "{:?}", runner.commands());
+    assert_eq!(store.load().unwrap().as_deref(), Some(HEAD));
+}
+
+#[tokio::test]
+async fn incremental_delete_removes_user_from_group() {
+    let _guard = global_lock();
+    let github = FakeGitHub::start().await;
+    github.head(HEAD).await;
+    github
+        .compare(json!([{ "filename": "access/host-1/proj/abc123", "status": "removed" }]))
+        .await;
+    // Deleted files are read at the base commit.
+    github.file_at("names/abc123", BASE, "alice\n").await;
+    let store = MemoryCommitStore::new(Some(BASE.to_string()));
+    let runner = RecordingRunner::new();
+
+    let report = run_sync(github.config(""), &store, &runner).await;
+
+    assert!(
+        runner.ran("sudo gpasswd -d alice proj"),
+        "{:?}",
+        runner.commands()
+    );
+    assert_eq!(report.groups_removed, 1);
+    assert_eq!(store.load().unwrap().as_deref(), Some(HEAD));
+}
+
+#[tokio::test]
+async fn unchanged_head_runs_nothing() {
+    let _guard = global_lock();
+    let github = FakeGitHub::start().await;
+    github.head(BASE).await;
+    let store = MemoryCommitStore::new(Some(BASE.to_string()));
+    let runner = RecordingRunner::new();
+
+    let report = run_sync(github.config(""), &store, &runner).await;
+
+    assert!(!report.changed);
+    assert!(runner.commands().is_empty());
+    assert_eq!(report.processed_commit, BASE);
+}
+
+#[tokio::test]
+async fn full_sync_without_pointer_grants_listed_users() {
+    let _guard = global_lock();
+    let github = FakeGitHub::start().await;
+    github.head(HEAD).await;
+    github
+        .dir("access", &[("host-1", "dir"), ("host-2", "dir")])
+        .await;
+    github.dir("access/host-1", &[("proj", "dir")]).await;
+    github
+        .dir("access/host-1/proj", &[("abc123", "file")])
+        .await;
+    github.file("names/abc123", "alice\n").await;
+    github.file("access/host-1/proj/abc123", "").await;
+    let store = MemoryCommitStore::new(None);
+    let runner = RecordingRunner::new();
+
+    let report = run_sync(github.config(""), &store, &runner).await;
+
+    assert!(report.errors.is_empty(), "{:?}", report.errors);
+    assert!(
+        runner.ran("sudo usermod -aG proj alice"),
+        "{:?}",
+        runner.commands()
+    );
+    assert_eq!(store.load().unwrap().as_deref(), Some(HEAD));
+}
+
+#[tokio::test]
+async fn full_sync_follows_paginated_listings() {
+    let _guard = global_lock();
+    let github = FakeGitHub::start().await;
+    github.head(HEAD).await;
+    github.dir("access", &[("host-1", "dir")]).await;
+    github.dir("access/host-1", &[("proj", "dir")]).await;
+    let listing = format!(
+        "{}{}/contents/access/host-1/proj",
+        github.server.uri(),
+        REPO
+    );
+    Mock::given(method("GET"))
+        .and(path(format!("{}/contents/access/host-1/proj", REPO)))
+        .and(query_param("page", "2"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!([{ "name": "hash2", "type": "file", "sha": blob_sha("2") }])),
+        )
+        .with_priority(1)
+        .mount(&github.server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!("{}/contents/access/host-1/proj", REPO)))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header(
+                    "link",
+                    format!("<{}?ref=main&page=2>; rel=\"next\"", listing),
+                )
+                .set_body_json(json!([{ "name": "hash1", "type": "file", "sha": blob_sha("1") }])),
+        )
+        .mount(&github.server)
+        .await;
+    github.file("names/hash1", "alice\n").await;
+    github.file("names/hash2", "bob\n").await;
+    let store = MemoryCommitStore::new(None);
+    let runner = RecordingRunner::new();
+
+    let report = run_sync(github.config(""), &store, &runner).await;
+
+    assert!(report.errors.is_empty(), "{:?}", report.errors);
+    assert!(
+        runner.ran("sudo usermod -aG proj alice"),
+        "{:?}",
+        runner.commands()
+    );
+    assert!(
+        runner.ran("sudo usermod -aG proj bob"),
+        "{:?}",
+        runner.commands()
+    );
+}
+
+fn entry(provider: &str, project: &str, hash: &str, status: FileStatus) -> DiffEntry {
+    DiffEntry {
+        provider: provider.to_string(),
+        project: project.to_string(),
+        hash: hash.to_string(),
+        status,
+        old_hash: None,
+    }
+}
+
+#[test]
+fn diff_parser_reads_mixed_add_delete_modify() {
+    let diff = "\
+diff --git a/access/aws/proj/aaa b/access/aws/proj/aaa
+new file mode 100644
+index 0000000..1111111
+--- /dev/null
++++ b/access/aws/proj/aaa
+@@ -0,0 +1 @@
++ssh-ed25519 AAAA
+diff --git a/access/aws/proj/bbb b/access/aws/proj/bbb
+deleted file mode 100644
+index 2222222..0000000
+--- a/access/aws/proj/bbb
++++ /dev/null
+@@ -1 +0,0 @@
+-ssh-ed25519 BBBB
+diff --git a/access/gcp/other/ccc b/access/gcp/other/ccc
+index 3333333..4444444 100644
+--- a/access/gcp/other/ccc
++++ b/access/gcp/other/ccc
+@@ -1 +1 @@
+-new file mode in the body is not a marker
++deleted file mode neither
+diff --git a/names/ddd b/names/ddd
+index 5555555..6666666 100644
+--- a/names/ddd
++++ b/names/ddd
+@@ -1 +1 @@
+-alice
++alicia
+";
+    assert_eq!(
+        extract_diff_parts(diff),
+        vec![
+            entry("aws", "proj", "aaa", FileStatus::Added),
+            entry("aws", "proj", "bbb", FileStatus::Deleted),
+            entry("gcp", "other", "ccc", FileStatus::Modified),
+            entry("", "names", "ddd", FileStatus::ModifiedUser),
+        ]
+    );
+}
+
+#[test]
+fn diff_parser_ignores_files_outside_access_and_names() {
+    let diff = "\
+diff --git a/README.md b/README.md
+index 1111111..2222222 100644
+--- a/README.md
++++ b/README.md
+";
+    assert!(extract_diff_parts(diff).is_empty());
+}
+
+#[tokio::test]
+async fn modified_access_file_revokes_dropped_users_and_regrants_the_rest() {
+    let _guard = global_lock();
+    let github = FakeGitHub::start().await;
+    github.head(HEAD).await;
+    github
+        .compare(json!([{ "filename": "access/host-1/proj/abc123", "status": "modified" }]))
+        .await;
+    github.file("names/abc123", "bob\n").await;
+    github.file_at("names/abc123", BASE, "alice\nbob\n").await;
+    let store = MemoryCommitStore::new(Some(BASE.to_string()));
+    let runner = RecordingRunner::new();
+
+    let report = run_sync(github.config(""), &store, &runner).await;
+
+    assert!(report.errors.is_empty(), "{:?}", report.errors);
+    assert!(
+        runner.ran("sudo gpasswd -d alice proj"),
+        "{:?}",
+        runner.commands()
+    );
+    assert!(!runner.ran("sudo gpasswd -d bob proj"));
+    assert!(runner.ran("sudo usermod -aG proj bob"));
+    assert_eq!(store.load().unwrap().as_deref(), Some(HEAD));
+}
+
+// `nobody` stands in for an existing account: user lookups go to the real
+// passwd database, only commands are mocked.
+#[tokio::test]
+async fn modified_names_file_renames_the_user() {
+    let _guard = global_lock();
+    let github = FakeGitHub::start().await;
+    github.head(HEAD).await;
+    github
+        .compare(json!([{ "filename": "names/abc123", "status": "modified" }]))
+        .await;
+    github.file("names/abc123", "watchdog-renamed\n").await;
+    github.file_at("names/abc123", BASE, "nobody\n").await;
+    let store = MemoryCommitStore::new(Some(BASE.to_string()));
+    let runner = RecordingRunner::new();
+
+    let report = run_sync(github.config(""), &store, &runner).await;
+
+    assert!(report.errors.is_empty(), "{:?}", report.errors);
+    assert!(
+        runner.ran(
+            "sudo usermod -l watchdog-renamed -d /opt/watchdog/users/watchdog-renamed -m nobody"
+        ),
+        "{:?}",
+        runner.commands()
+    );
+    assert!(report.changed);
+}
+
+#[tokio::test]
+async fn large_files_are_fetched_through_the_blob_api() {
+    let github = FakeGitHub::start().await;
+    let big = "alice\n".repeat(200_000);
+    let blob_url = format!("{}{}/git/blobs/{}", github.server.uri(), REPO, HEAD);
+    // Files over 1MB come back from /contents without inline content.
+    Mock::given(method("GET"))
+        .and(path(format!("{}/contents/names/abc123", REPO)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "name": "abc123",
+            "type": "file",
+            "sha": HEAD,
+            "size": big.len(),
+            "encoding": "none",
+            "content": "",
+            "git_url": blob_url,
+        })))
+        .mount(&github.server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!("{}/git/blobs/{}", REPO, HEAD)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "sha": HEAD,
+            "size": big.len(),
+            "encoding": "base64",
+            "content": STANDARD.encode(&big),
+        })))
+        .mount(&github.server)
+        .await;
+    let client = GitHubClient::new(&github.config("")).unwrap();
+
+    let decoded = fetch_and_decode_path(&client, "names/abc123", "main")
+        .await
+        .unwrap()
+        .expect("blob content");
+
+    assert_eq!(decoded.content, big);
+    assert_eq!(decoded.sha, HEAD);
+}
+
+#[tokio::test]
+async fn invalid_utf8_content_is_skipped() {
+    let github = FakeGitHub::start().await;
+    Mock::given(method("GET"))
+        .and(path(format!("{}/contents/names/abc123", REPO)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "name": "abc123",
+            "type": "file",
+            "sha": HEAD,
+            "encoding": "base64",
+            "content": STANDARD.encode([0x61, 0xff, 0xfe, 0x0a]),
+        })))
+        .mount(&github.server)
+        .await;
+    let client = GitHubClient::new(&github.config("")).unwrap();
+
+    let decoded = fetch_and_decode_path(&client, "names/abc123", "main").await;
+
+    assert!(
+        matches!(decoded, Ok(None)),
+        "{:?}",
+        decoded.map(|d| d.map(|d| d.content))
+    );
+}
+
+#[tokio::test]
+async fn compare_fetch_retries_a_transient_503() {
+    let github = FakeGitHub::start().await;
+    Mock::given(method("GET"))
+        .and(path(format!("{}/compare/{}...{}", REPO, BASE, HEAD)))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .expect(1)
+        .mount(&github.server)
+        .await;
+    github
+        .compare(json!([{ "filename": "access/host-1/proj/abc123", "status": "added" }]))
+        .await;
+    let client = GitHubClient::new(&github.config("transient_retries = 2")).unwrap();
+
+    let entries = fetch_diff_entries(&client, BASE, HEAD).await.unwrap();
+
+    assert_eq!(
+        entries,
+        vec![entry("host-1", "proj", "abc123", FileStatus::Added)]
+    );
+}
+
+#[tokio::test]
+async fn compare_fetch_gives_up_after_the_configured_retries() {
+    let github = FakeGitHub::start().await;
+    Mock::given(method("GET"))
+        .and(path(format!("{}/compare/{}...{}", REPO, BASE, HEAD)))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(2)
+        .mount(&github.server)
+        .await;
+    let client = GitHubClient::new(&github.config("transient_retries = 1")).unwrap();
+
+    assert!(fetch_diff_entries(&client, BASE, HEAD).await.is_err());
+}
+
+#[test]
+fn rename_within_a_project_is_a_key_rotation() {
+    let diff = "\
+diff --git a/access/aws/proj/old b/access/aws/proj/new
+similarity index 100%
+rename from access/aws/proj/old
+rename to access/aws/proj/new
+";
+    assert_eq!(
+        extract_diff_parts(diff),
+        vec![DiffEntry {
+            old_hash: Some("old".to_string()),
+            ..entry("aws", "proj", "new", FileStatus::Renamed)
+        }]
+    );
+}
+
+#[test]
+fn rename_across_projects_revokes_and_grants() {
+    let compare = json!({ "files": [{
+        "filename": "access/aws/other/abc",
+        "previous_filename": "access/aws/proj/abc",
+        "status": "renamed",
+    }]});
+    assert_eq!(
+        entries_from_compare_json(&compare),
+        vec![
+            entry("aws", "proj", "abc", FileStatus::Deleted),
+            entry("aws", "other", "abc", FileStatus::Added),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn full_sync_only_reads_this_hosts_provider() {
+    let _guard = global_lock();
+    let github = FakeGitHub::start().await;
+    github.head(HEAD).await;
+    github
+        .dir("access", &[("host-1", "dir"), ("host-2", "dir")])
+        .await;
+    github.dir("access/host-1", &[("proj", "dir")]).await;
+    github.dir("access/host-1/proj", &[("hash1", "file")]).await;
+    github.file("names/hash1", "alice\n").await;
+    Mock::given(method("GET"))
+        .and(path(format!("{}/contents/access/host-2", REPO)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+        .expect(0)
+        .mount(&github.server)
+        .await;
+    let store = MemoryCommitStore::new(None);
+    let runner = RecordingRunner::new();
+
+    let report = run_sync(github.config(""), &store, &runner).await;
+
+    assert!(report.errors.is_empty(), "{:?}", report.errors);
+    assert!(runner.ran("sudo usermod -aG proj alice"));
+    assert_eq!(
+        runner
+            .commands()
+            .iter()
+            .filter(|c| c.starts_with("sudo usermod -aG"))
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn file_added_and_deleted_in_range_yields_no_entry() {
+    let compare = json!({ "files": [
+        { "filename": "access/aws/proj/abc", "status": "added" },
+        { "filename": "access/aws/proj/abc", "status": "removed" },
+        { "filename": "access/aws/proj/keep", "status": "added" },
+    ]});
+    assert_eq!(
+        entries_from_compare_json(&compare),
+        vec![entry("aws", "proj", "keep", FileStatus::Added)]
+    );
+}
+
+#[test]
+fn file_deleted_then_added_again_is_a_refresh() {
+    let compare = json!({ "files": [
+        { "filename": "access/aws/proj/abc", "status": "removed" },
+        { "filename": "access/aws/proj/abc", "status": "added" },
+    ]});
+    assert_eq!(
+        entries_from_compare_json(&compare),
+        vec![entry("aws", "proj", "abc", FileStatus::Modified)]
+    );
+}
+
+#[tokio::test]
+async fn each_repo_keeps_its_own_commit_pointer() {
+    let _guard = global_lock();
+    let infra = FakeGitHub::start().await;
+    infra.head(HEAD).await;
+    infra.compare(json!([])).await;
+    let apps = FakeGitHub::start().await;
+    apps.head(BASE).await;
+    let state_dir = TempDir::new().unwrap();
+    for name in ["infra", "apps"] {
+        fs::create_dir_all(state_dir.path().join(name)).unwrap();
+        fs::write(state_dir.path().join(name).join(COMMIT_FILE_NAME), BASE).unwrap();
+    }
+    let keyhouse_config = infra.config(&format!(
+        "[[repos]]\nname = \"infra\"\nbase_url = \"{}{}\"\n\
+         [[repos]]\nname = \"apps\"\nbase_url = \"{}{}\"\n",
+        infra.server.uri(),
+        REPO,
+        apps.server.uri(),
+        REPO
+    ));
+    let keyhouse_config = KeyhouseConf {
+        state_dir: Some(state_dir.path().to_path_buf()),
+        ..keyhouse_config
+    };
+    let runner = RecordingRunner::new();
+    install(&runner, (&keyhouse_config).into());
+
+    let report = process_all_repos(keyhouse_config, "watchdog-test", HOST.to_string())
+        .await
+        .expect("sync");
+
+    assert!(report.errors.is_empty(), "{:?}", report.errors);
+    let pointer = |name: &str| {
+        FileCommitStore::new(state_dir.path().join(name).join(COMMIT_FILE_NAME))
+            .load()
+            .unwrap()
+    };
+    assert_eq!(pointer("infra").as_deref(), Some(HEAD));
+    assert_eq!(pointer("apps").as_deref(), Some(BASE));
+}
+
+#[test]
+fn diff_parser_accepts_dotted_projects_and_suffixed_hashes() {
+    let diff = "\
+diff --git a/access/aws/my.project/ab12cd.pub b/access/aws/my.project/ab12cd.pub
+new file mode 100644
+--- /dev/null
++++ b/access/aws/my.project/ab12cd.pub
+diff --git a/access/aws/web-app/ef34-56 b/access/aws/web-app/ef34-56
+new file mode 100644
+--- /dev/null
++++ b/access/aws/web-app/ef34-56
+";
+    assert_eq!(
+        extract_diff_parts(diff),
+        vec![
+            entry("aws", "my.project", "ab12cd.pub", FileStatus::Added),
+            entry("aws", "web-app", "ef34-56", FileStatus::Added),
+        ]
+    );
+}
+
+#[test]
+fn diff_parser_rejects_path_traversal() {
+    let diff = "\
+diff --git a/access/aws/../names/abc b/access/aws/../names/abc
+new file mode 100644
+--- /dev/null
++++ b/access/aws/../names/abc
+";
+    assert!(extract_diff_parts(diff).is_empty());
+}
+
+#[test]
+fn names_file_content_is_trimmed_to_the_username() {
+    assert_eq!(normalize_username("alice\n").unwrap(), "alice");
+    assert_eq!(normalize_username("  alice \r\n").unwrap(), "alice");
+    assert!(normalize_username("alice\nbob\n").is_err());
+    assert!(normalize_username("al ice").is_err());
+    assert!(normalize_username("\n").is_err());
+}
+
+#[tokio::test]
+async fn multi_user_file_applies_valid_lines_despite_an_invalid_one() {
+    let _guard = global_lock();
+    let github = FakeGitHub::start().await;
+    github.head(HEAD).await;
+    github
+        .compare(json!([{ "filename": "access/host-1/proj/abc123", "status": "added" }]))
+        .await;
+    github
+        .file("names/abc123", "# team\nalice\nBad User!\n\nbob\n")
+        .await;
+    github.file("access/host-1/proj/abc123", "").await;
+    let store = MemoryCommitStore::new(Some(BASE.to_string()));
+    let runner = RecordingRunner::new();
+
+    let report = run_sync(github.config(""), &store, &runner).await;
+
+    assert!(runner.ran("sudo usermod -aG proj alice"));
+    assert!(runner.ran("sudo usermod -aG proj bob"));
+    assert!(!runner.commands().iter().any(|run| run.contains("Bad")));
+    assert_eq!(report.groups_added, 2);
+    assert_eq!(report.errors.len(), 1, "{:?}", report.errors);
+}
+
+#[test]
+fn orphans_are_managed_users_missing_from_the_repo() {
+    let managed: Vec<String> = ["alice", "bob", "carol"].map(String::from).to_vec();
+    let seen: BTreeSet<String> = ["alice", "carol", "dave"].map(String::from).into();
+    assert_eq!(find_orphans(&managed, &seen), vec!["bob"]);
+    assert!(find_orphans(&managed, &managed.iter().cloned().collect()).is_empty());
+}
+
+#[test]
+fn pruning_more_orphans_than_max_deletions_deletes_nothing() {
+    let _guard = global_lock();
+    let home_base = TempDir::new().unwrap();
+    for user in ["alice", "bob", "carol"] {
+        fs::create_dir(home_base.path().join(user)).unwrap();
+    }
+    let runner = RecordingRunner::new();
+    install(
+        &runner,
+        UserConf {
+            home_base: home_base.path().to_string_lossy().into_owned(),
+            ..UserConf::default()
+        },
+    );
+    let seen: BTreeSet<String> = ["alice".to_string()].into();
+    let mut report = SyncReport::default();
+
+    prune_orphans(&mut report, &seen, 1);
+
+    assert!(runner.commands().is_empty(), "{:?}", runner.commands());
+    assert_eq!(report.users_deleted, 0);
+    assert_eq!(report.errors.len(), 1, "{:?}", report.errors);
+}
+
+#[tokio::test]
+async fn replay_applies_the_range_and_persists_only_when_asked() {
+    let _guard = global_lock();
+    let stored = "3333333333333333333333333333333333333333";
+    for persist in [false, true] {
+        let github = FakeGitHub::start().await;
+        github.head(HEAD).await;
+        github
+            .compare(json!([{ "filename": "access/host-1/proj/abc123", "status": "added" }]))
+            .await;
+        github.file("names/abc123", "alice\n").await;
+        github.file("access/host-1/proj/abc123", "").await;
+        let store = MemoryCommitStore::new(Some(stored.to_string()));
+        let runner = RecordingRunner::new();
+        let keyhouse_config = github.config("");
+        install(&runner, (&keyhouse_config).into());
+
+        sync_from(
+            keyhouse_config,
+            "watchdog-test",
+            HOST.to_string(),
+            BASE,
+            persist,
+            &store,
+        )
+        .await
+        .expect("replay");
+
+        assert!(runner.ran("sudo usermod -aG proj alice"));
+        let expected = if persist { HEAD } else { stored };
+        assert_eq!(store.load().unwrap().as_deref(), Some(expected));
+    }
+}
+
+#[test]
+fn compare_json_payload_maps_onto_diff_entries() {
+    let compare: Value =
+        serde_json::from_str(include_str!("testdata/compare.json")).expect("compare fixture");
+    assert_eq!(
+        entries_from_compare_json(&compare),
+        vec![
+            entry("aws", "proj", "b0b0b0", FileStatus::Added),
+            entry("", "names", "b0b0b0", FileStatus::ModifiedUser),
+            DiffEntry {
+                old_hash: Some("ca401b".to_string()),
+                ..entry("gcp", "web.app", "ca401c", FileStatus::Renamed)
+            },
+            entry("aws", "proj", "da7eda", FileStatus::Deleted),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn full_sync_pointer_falls_back_to_the_commits_listing() {
+    let _guard = global_lock();
+    let github = FakeGitHub::start().await;
+    github.head(HEAD).await;
+    github.dir("access", &[]).await;
+    Mock::given(method("GET"))
+        .and(path(format!("{}/commits/main", REPO)))
+        .respond_with(ResponseTemplate::new(502))
+        .expect(2)
+        .mount(&github.server)
+        .await;
+    let store = MemoryCommitStore::new(None);
+    let runner = RecordingRunner::new();
+
+    let report = run_sync(github.config("transient_retries = 1"), &store, &runner).await;
+
+    assert!(report.errors.is_empty(), "{:?}", report.errors);
+    assert_eq!(store.load().unwrap().as_deref(), Some(HEAD));
+}
+
+#[tokio::test]
+async fn full_sync_pointer_prefers_the_branch_head() {
+    let _guard = global_lock();
+    let branch_head = "4444444444444444444444444444444444444444";
+    let github = FakeGitHub::start().await;
+    github.head(HEAD).await;
+    github.dir("access", &[]).await;
+    Mock::given(method("GET"))
+        .and(path(format!("{}/commits/main", REPO)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "sha": branch_head })))
+        .mount(&github.server)
+        .await;
+    let store = MemoryCommitStore::new(None);
+    let runner = RecordingRunner::new();
+
+    run_sync(github.config(""), &store, &runner).await;
+
+    assert_eq!(store.load().unwrap().as_deref(), Some(branch_head));
+}
+
+#[test]
+fn diff_status_does_not_depend_on_header_line_order() {
+    let marker_first = "\
+diff --git a/access/aws/proj/aaa b/access/aws/proj/aaa
+new file mode 100644
+index 0000000..1111111
+--- /dev/null
++++ b/access/aws/proj/aaa
+diff --git a/access/aws/proj/bbb b/access/aws/proj/bbb
+deleted file mode 100644
+index 2222222..0000000
+--- a/access/aws/proj/bbb
++++ /dev/null
+";
+    let marker_last = "\
+diff --git a/access/aws/proj/aaa b/access/aws/proj/aaa
+index 0000000..1111111
+new file mode 100644
+--- /dev/null
++++ b/access/aws/proj/aaa
+diff --git a/access/aws/proj/bbb b/access/aws/proj/bbb
+index 2222222..0000000
+deleted file mode 100644
+--- a/access/aws/proj/bbb
++++ /dev/null
+";
+    let expected = vec![
+        entry("aws", "proj", "aaa", FileStatus::Added),
+        entry("aws", "proj", "bbb", FileStatus::Deleted),
+    ];
+    assert_eq!(extract_diff_parts(marker_first), expected);
+    assert_eq!(extract_diff_parts(marker_last), expected);
+}
+
+#[test]
+fn markers_after_the_header_are_content() {
+    let diff = "\
+diff --git a/access/aws/proj/aaa b/access/aws/proj/aaa
+index 1111111..2222222 100644
+--- a/access/aws/proj/aaa
++++ b/access/aws/proj/aaa
+@@ -1 +1,2 @@
+ ssh-ed25519 AAAA
++new file mode 100644
+deleted file mode 100644
+";
+    assert_eq!(
+        extract_diff_parts(diff),
+        vec![entry("aws", "proj", "aaa", FileStatus::Modified)]
+    );
+}
+
+#[tokio::test]
+async fn allowed_providers_filter_matching_hosts_files() {
+    let _guard = global_lock();
+    let github = FakeGitHub::start().await;
+    github.head(HEAD).await;
+    github
+        .compare(json!([
+            { "filename": "access/host/proj/abc123", "status": "added" },
+            { "filename": "access/host-1/proj/def456", "status": "added" },
+        ]))
+        .await;
+    github.file("names/def456", "alice\n").await;
+    github.file("access/host-1/proj/def456", "").await;
+    let store = MemoryCommitStore::new(Some(BASE.to_string()));
+    let runner = RecordingRunner::new();
+
+    let report = run_sync(
+        github.config("provider_match = \"prefix\"\nallowed_providers = [\"host-1\"]"),
+        &store,
+        &runner,
+    )
+    .await;
+
+    assert!(report.errors.is_empty(), "{:?}", report.errors);
+    assert_eq!(report.skipped_other_host, 1);
+    assert!(runner.ran("sudo usermod -aG proj alice"));
+    assert_eq!(
+        runner
+            .commands()
+            .iter()
+            .filter(|run| run.starts_with("sudo usermod -aG"))
+            .count(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn full_sync_skips_nested_directories_in_a_project() {
+    let _guard = global_lock();
+    let github = FakeGitHub::start().await;
+    github.head(HEAD).await;
+    github.dir("access", &[("host-1", "dir")]).await;
+    github.dir("access/host-1", &[("proj", "dir")]).await;
+    github
+        .dir(
+            "access/host-1/proj",
+            &[("nested", "dir"), ("abc123", "file")],
+        )
+        .await;
+    github.file("names/abc123", "alice\n").await;
+    github.file("access/host-1/proj/abc123", "").await;
+    Mock::given(method("GET"))
+        .and(path(format!("{}/contents/names/nested", REPO)))
+        .respond_with(ResponseTemplate::new(404))
+        .expect(0)
+        .mount(&github.server)
+        .await;
+    let store = MemoryCommitStore::new(None);
+    let runner = RecordingRunner::new();
+
+    let report = run_sync(github.config(""), &store, &runner).await;
+
+    assert!(report.errors.is_empty(), "{:?}", report.errors);
+    assert!(runner.ran("sudo usermod -aG proj alice"));
+    assert_eq!(store.load().unwrap().as_deref(), Some(HEAD));
+}
+
+#[tokio::test]
+async fn incremental_sync_never_fetches_other_hosts_files() {
+    let _guard = global_lock();
+    let github = FakeGitHub::start().await;
+    github.head(HEAD).await;
+    github
+        .compare(json!([{ "filename": "access/host-2/proj/abc123", "status": "added" }]))
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!("{}/contents/names/abc123", REPO)))
+        .respond_with(ResponseTemplate::new(404))
+        .expect(0)
+        .mount(&github.server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!("{}/contents/access/host-2/proj/abc123", REPO)))
+        .respond_with(ResponseTemplate::new(404))
+        .expect(0)
+        .mount(&github.server)
+        .await;
+    let store = MemoryCommitStore::new(Some(BASE.to_string()));
+    let runner = RecordingRunner::new();
+
+    let report = run_sync(github.config(""), &store, &runner).await;
+
+    assert_eq!(report.skipped_other_host, 1);
+}
+
+#[tokio::test]
+async fn newline_terminated_commit_file_builds_a_clean_compare_url() {
+    let _guard = global_lock();
+    let github = FakeGitHub::start().await;
+    github.head(HEAD).await;
+    github
+        .compare(json!([{ "filename": "access/host-1/proj/abc123", "status": "added" }]))
+        .await;
+    github.file("names/abc123", "alice\n").await;
+    github.file("access/host-1/proj/abc123", "").await;
+    let keyhouse_config = github.config("");
+    fs::write(keyhouse_config.commit_file_path(), format!("{}\n", BASE)).unwrap();
+    let store = FileCommitStore::new(keyhouse_config.commit_file_path());
+    let runner = RecordingRunner::new();
+    install(&runner, (&keyhouse_config).into());
+
+    let report = process_update_request(keyhouse_config, "watchdog-test", HOST.to_string(), &store)
+        .await
+        .expect("sync");
+
+    assert!(report.errors.is_empty(), "{:?}", report.errors);
+    assert!(runner.ran("sudo usermod -aG proj alice"));
+    assert_eq!(store.load().unwrap().as_deref(), Some(HEAD));
+}
+
+#[tokio::test]
+async fn failed_full_sync_leaves_the_pointer_unset() {
+    let _guard = global_lock();
+    let github = FakeGitHub::start().await;
+    github.head(HEAD).await;
+    Mock::given(method("GET"))
+        .and(path(format!("{}/contents/access", REPO)))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&github.server)
+        .await;
+    let store = MemoryCommitStore::new(None);
+    let runner = RecordingRunner::new();
+
+    let report = run_sync(github.config(""), &store, &runner).await;
+
+    assert!(!report.errors.is_empty());
+    assert_eq!(store.load().unwrap(), None);
+    assert!(report.processed_commit.is_empty());
+}
+
+#[tokio::test]
+async fn unreadable_grants_above_the_threshold_leave_the_pointer_unset() {
+    let _guard = global_lock();
+    let github = FakeGitHub::start().await;
+    github.head(HEAD).await;
+    github.dir("access", &[("host-1", "dir")]).await;
+    github.dir("access/host-1", &[("proj", "dir")]).await;
+    Mock::given(method("GET"))
+        .and(path(format!("{}/contents/access/host-1/proj", REPO)))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&github.server)
+        .await;
+    let store = MemoryCommitStore::new(None);
+    let runner = RecordingRunner::new();
+
+    run_sync(
+        github.config("transient_retries = 0\nfull_sync_max_errors = 0"),
+        &store,
+        &runner,
+    )
+    .await;
+
+    assert_eq!(store.load().unwrap(), None);
+}
+
+#[tokio::test]
+async fn empty_diff_reports_no_change() {
+    let _guard = global_lock();
+    let github = FakeGitHub::start().await;
+    github.head(HEAD).await;
+    github.compare(json!([])).await;
+    let store = MemoryCommitStore::new(Some(BASE.to_string()));
+    let runner = RecordingRunner::new();
+
+    let report = run_sync(github.config(""), &store, &runner).await;
+
+    assert!(!report.changed);
+    assert!(report.errors.is_empty(), "{:?}", report.errors);
+    assert!(runner.commands().is_empty());
+    assert_eq!(store.load().unwrap().as_deref(), Some(HEAD));
+}
+
+#[tokio::test]
+async fn re_adding_an_existing_membership_reports_no_change() {
+    let _guard = global_lock();
+    let github = FakeGitHub::start().await;
+    github.head(HEAD).await;
+    github
+        .compare(json!([{ "filename": "access/host-1/root/abc123", "status": "modified" }]))
+        .await;
+    github.file("names/abc123", "root\n").await;
+    github.file_at("names/abc123", BASE, "root\n").await;
+    github.file("access/host-1/root/abc123", "").await;
+    let store = MemoryCommitStore::new(Some(BASE.to_string()));
+    // root is already in its own group, so the grant changes nothing.
+    let runner = RecordingRunner::new();
+
+    let report = run_sync(github.config(""), &store, &runner).await;
+
+    assert!(!report.changed, "{:?}", runner.commands());
+}
+
+#[tokio::test]
+async fn empty_commit_list_is_a_typed_error() {
+    let github = FakeGitHub::start().await;
+    Mock::given(method("GET"))
+        .and(path(format!("{}/commits", REPO)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+        .mount(&github.server)
+        .await;
+    let client = GitHubClient::new(&github.config("")).unwrap();
+
+    let err = fetch_recent_commit(&client).await.unwrap_err();
+
+    assert!(
+        matches!(&err, WatchdogError::NoCommits { branch, skip: 0 } if branch == "main"),
+        "{:?}",
+        err
+    );
+}
+
+#[tokio::test]
+async fn commit_skip_selects_an_older_page() {
+    let github = FakeGitHub::start().await;
+    Mock::given(method("GET"))
+        .and(path(format!("{}/commits", REPO)))
+        .and(query_param("sha", "main"))
+        .and(query_param("per_page", "1"))
+        .and(query_param("page", "3"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([{ "sha": BASE }])))
+        .mount(&github.server)
+        .await;
+    let client = GitHubClient::new(&github.config("commit_skip = 2")).unwrap();
+
+    assert_eq!(fetch_recent_commit(&client).await.unwrap(), BASE);
+}
+
+// A ref with `&` or `=` must reach GitHub as one `ref` value rather than
+// splitting into extra query parameters.
+#[tokio::test]
+async fn refs_are_percent_encoded_in_contents_queries() {
+    let github = FakeGitHub::start().await;
+    let branch = "release/1.0&page=9";
+    github.file_at("names/abc123", branch, "alice\n").await;
+    Mock::given(method("GET"))
+        .and(path(format!("{}/contents/access", REPO)))
+        .and(query_param("ref", branch))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(
+                json!([{ "name": "host-1", "type": "dir", "sha": blob_sha("host-1") }]),
+            ),
+        )
+        .expect(1)
+        .mount(&github.server)
+        .await;
+    let mut config = github.config("");
+    config.branch = branch.to_string();
+    let client = GitHubClient::new(&config).unwrap();
+
+    let listing: Vec<Value> = list_repo_dir(&client, &["access"]).await.unwrap();
+    let decoded = fetch_and_decode_path(&client, "names/abc123", branch)
+        .await
+        .unwrap()
+        .expect("file content");
+
+    assert_eq!(listing.len(), 1);
+    assert_eq!(decoded.content, "alice\n");
+}
+
+#[tokio::test]
+async fn branch_is_one_encoded_segment_of_the_commit_path() {
+    let github = FakeGitHub::start().await;
+    Mock::given(method("GET"))
+        .and(path(format!("{}/commits/release%2F1%2E0", REPO)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "sha": HEAD })))
+        .expect(1)
+        .mount(&github.server)
+        .await;
+    let mut config = github.config("");
+    config.branch = "release/1.0".to_string();
+    let client = GitHubClient::new(&config).unwrap();
+
+    assert_eq!(fetch_latest_commit(&client).await.unwrap(), HEAD);
+}
+
+// User lookups read the real passwd database, where the recorded useradd never
+// lands, so the rollback itself is exercised on an account that does exist.
+#[test]
+fn rollback_deletes_a_half_provisioned_user_only_when_enabled() {
+    let _guard = global_lock();
+    for rollback in [false, true] {
+        let runner = RecordingRunner::new();
+        install(
+            &runner,
+            UserConf {
+                rollback_on_partial_failure: rollback,
+                ..UserConf::default()
+            },
+        );
+        let mut report = SyncReport::default();
+
+        roll_back_created_user(&mut report, "nobody");
+
+        assert_eq!(runner.ran("sudo userdel -r nobody"), rollback);
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+    }
+}
+
+#[tokio::test]
+async fn failed_group_add_for_a_new_user_is_reported() {
+    let _guard = global_lock();
+    let github = FakeGitHub::start().await;
+    github.head(HEAD).await;
+    github
+        .compare(json!([{ "filename": "access/host-1/proj/abc123", "status": "added" }]))
+        .await;
+    github.file("names/abc123", "alice\n").await;
+    github.file("access/host-1/proj/abc123", "").await;
+    let store = MemoryCommitStore::new(Some(BASE.to_string()));
+    let runner = RecordingRunner::with_responder(|program, args| match (program, args) {
+        ("sudo", ["usermod", ..]) => output(1, ""),
+        _ => output(0, ""),
+    });
+
+    let report = run_sync(
+        github.config("rollback_on_partial_failure = true"),
+        &store,
+        &runner,
+    )
+    .await;
+
+    assert!(runner.ran("sudo usermod -aG proj alice"));
+    assert_eq!(report.groups_added, 0);
+    assert_eq!(report.errors.len(), 1, "{:?}", report.errors);
+    // The recorded useradd never created alice, so there is nothing to undo.
+    assert!(!runner.ran_starting_with("sudo userdel"));
+}
+
+#[test]
+fn project_meta_parses_its_schema() {
+    assert_eq!(
+        parse_project_meta(r#"{ "grant_sudo": true, "shell": "/bin/zsh" }"#).unwrap(),
+        ProjectMeta {
+            grant_sudo: true,
+            shell: Some("/bin/zsh".to_string()),
+        }
+    );
+    assert_eq!(parse_project_meta("{}").unwrap(), ProjectMeta::default());
+}
+
+#[test]
+fn project_meta_rejects_bad_input() {
+    assert!(parse_project_meta(r#"{ "shell": "zsh" }"#).is_err());
+    assert!(parse_project_meta(r#"{ "grant_sudo": "yes" }"#).is_err());
+    assert!(parse_project_meta("not json").is_err());
+}
+
+#[test]
+fn meta_json_is_not_treated_as_a_user_hash() {
+    let compare = json!({ "files": [
+        { "filename": "access/aws/proj/meta.json", "status": "modified" },
+        { "filename": "access/aws/proj/abc", "status": "added" },
+    ]});
+    assert_eq!(
+        entries_from_compare_json(&compare),
+        vec![
+            entry("aws", "proj", "meta.json", FileStatus::ModifiedMeta),
+            entry("aws", "proj", "abc", FileStatus::Added),
+        ]
+    );
+}
+
+#[test]
+fn startup_delay_is_reproducible_under_a_seeded_rng() {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    let jitter = Duration::from_secs(30);
+
+    let first = startup_delay(jitter, &mut StdRng::seed_from_u64(7));
+    let second = startup_delay(jitter, &mut StdRng::seed_from_u64(7));
+
+    assert_eq!(first, second);
+    assert!(first < jitter);
+    let mut rng = StdRng::seed_from_u64(7);
+    assert!((0..100).all(|_| startup_delay(jitter, &mut rng) < jitter));
+}
+
+#[test]
+fn zero_jitter_means_no_delay() {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    let mut rng = StdRng::seed_from_u64(7);
+    assert_eq!(startup_delay(Duration::ZERO, &mut rng), Duration::ZERO);
+}
+
+#[tokio::test]
+async fn unreachable_base_falls_back_to_a_full_sync() {
+    let _guard = global_lock();
+    let github = FakeGitHub::start().await;
+    github.head(HEAD).await;
+    Mock::given(method("GET"))
+        .and(path(format!("{}/compare/{}...{}", REPO, BASE, HEAD)))
+        .respond_with(
+            ResponseTemplate::new(422).set_body_json(json!({ "message": "No common ancestor" })),
+        )
+        .mount(&github.server)
+        .await;
+    github.dir("access", &[("host-1", "dir")]).await;
+    github.dir("access/host-1", &[("proj", "dir")]).await;
+    github
+        .dir("access/host-1/proj", &[("abc123", "file")])
+        .await;
+    github.file("names/abc123", "alice\n").await;
+    github.file("access/host-1/proj/abc123", "").await;
+    let store = MemoryCommitStore::new(Some(BASE.to_string()));
+    let runner = RecordingRunner::new();
+
+    let report = run_sync(github.config(""), &store, &runner).await;
+
+    assert!(report.errors.is_empty(), "{:?}", report.errors);
+    assert!(runner.ran("sudo usermod -aG proj alice"));
+    assert_eq!(store.load().unwrap().as_deref(), Some(HEAD));
+}
+
+#[tokio::test]
+async fn rate_limited_commands_leave_the_runtime_responsive() {
+    let _guard = global_lock();
+    let github = FakeGitHub::start().await;
+    github.head(HEAD).await;
+    github
+        .compare(json!([{ "filename": "access/host-1/proj/abc123", "status": "added" }]))
+        .await;
+    github.file("names/abc123", "alice\n").await;
+    github
+        .file("access/host-1/proj/abc123", "ssh-ed25519 AAAAkey alice\n")
+        .await;
+    let store = MemoryCommitStore::new(Some(BASE.to_string()));
+    let runner = RecordingRunner::new();
+    let tick = Duration::from_millis(10);
+    let mut ticks = 0u32;
+    let started = std::time::Instant::now();
+
+    let ticker = async {
+        loop {
+            tokio::time::sleep(tick).await;
+            ticks += 1;
+        }
+    };
+    tokio::select! {
+        _ = run_sync(github.config("max_commands_per_sec = 4.0"), &store, &runner) => {}
+        _ = ticker => {}
+    }
+
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(500), "{:?}", elapsed);
+    // A runtime stalled by the limiter would barely tick while it waits.
+    let expected = (elapsed.as_millis() / tick.as_millis()) as u32;
+    assert!(ticks * 2 >= expected, "{} ticks in {:?}", ticks, elapsed);
+}
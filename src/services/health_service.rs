@@ -0,0 +1,110 @@
+use crate::config::{KeyhouseConf, get_log_target, log_commit};
+use crate::error::Result;
+use crate::models::sync_status::SyncStatus;
+use crate::services::github_service::last_sync_status;
+use crate::services::shutdown::wait_for_shutdown;
+use log::{info, warn};
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+
+/// How long a connection gets to send its request line before it is dropped.
+const REQUEST_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Checks one repo's persisted sync status: ready when the last sync finished
+/// without errors less than `max_age_secs` ago.
+pub fn check_readiness(
+    status: Option<&SyncStatus>,
+    now: u64,
+    max_age_secs: u64,
+) -> std::result::Result<String, String> {
+    let Some(status) = status else {
+        return Err("no completed sync yet".to_string());
+    };
+    let age = now.saturating_sub(status.synced_at);
+    if age > max_age_secs {
+        Err(format!(
+            "last sync of {} was {}s ago (max {}s)",
+            status.commit, age, max_age_secs
+        ))
+    } else if status.error_count > 0 {
+        Err(format!(
+            "last sync of {} had {} errors",
+            status.commit, status.error_count
+        ))
+    } else {
+        Ok(format!("last sync of {} was {}s ago", status.commit, age))
+    }
+}
+
+fn readiness(keyhouse_config: &KeyhouseConf) -> std::result::Result<String, String> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    let mut details = Vec::new();
+    for repo_config in keyhouse_config.repo_configs() {
+        let status = last_sync_status(&repo_config);
+        details.push(check_readiness(
+            status.as_ref(),
+            now,
+            keyhouse_config.readiness_max_age_secs,
+        )?);
+    }
+    Ok(details.join("; "))
+}
+
+/// Serves `/healthz` (always 200 while the process runs) and `/readyz` (200,
+/// or 503 when a repo's last sync is too old or had errors) until shutdown.
+pub async fn serve_health(keyhouse_config: KeyhouseConf, listen_addr: &str) -> Result<()> {
+    let keyhouse_config = Arc::new(keyhouse_config);
+    let listener = TcpListener::bind(listen_addr).await?;
+    info!(target:get_log_target(), commit = log_commit(); "Health endpoint listening on {}", listen_addr);
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => accepted?.0,
+            _ = wait_for_shutdown() => return Ok(()),
+        };
+        // One task per connection, so a client that never sends its request
+        // can't hold up the probes behind it.
+        let keyhouse_config = Arc::clone(&keyhouse_config);
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &keyhouse_config).await {
+                warn!(target:get_log_target(), commit = log_commit(); "Health request failed: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, keyhouse_config: &KeyhouseConf) -> Result<()> {
+    let mut buffer = [0u8; 1024];
+    let read = timeout(REQUEST_READ_TIMEOUT, stream.read(&mut buffer))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "timed out reading the request"))??;
+    let request = String::from_utf8_lossy(&buffer[..read]);
+    let path = request
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .unwrap_or("");
+    let (status, body) = match path {
+        "/healthz" => ("200 OK", "ok".to_string()),
+        "/readyz" => match readiness(keyhouse_config) {
+            Ok(detail) => ("200 OK", detail),
+            Err(detail) => ("503 Service Unavailable", detail),
+        },
+        _ => ("404 Not Found", "not found".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
@@ -1,2 +1,17 @@
+pub mod command_runner;
+pub mod commit_store;
+pub mod content_cache;
+#[cfg(feature = "github-app")]
+pub mod github_app;
+pub mod github_auth;
+pub mod github_client;
 pub mod github_service;
+#[cfg(feature = "health")]
+pub mod health_service;
+pub mod preflight_service;
+pub mod provider_match;
+pub mod repo_api;
+pub mod repo_layout;
+pub mod shutdown;
 pub mod user_service;
+pub mod webhook_service;
@@ -0,0 +1,160 @@
+use crate::config::{KeyhouseConf, get_log_target, log_commit};
+use crate::error::Result;
+use crate::models::preflight_report::PreflightReport;
+use crate::services::command_runner::set_privilege_command;
+use crate::services::github_auth::{
+    OAUTH_SCOPES_HEADER, TOKEN_EXPIRATION_HEADER, has_read_scope, parse_token_expiration,
+};
+use crate::services::github_client::{GitHubClient, error_from_response, send_with_ratelimit};
+use crate::services::user_service::can_escalate;
+use log::{info, warn};
+use percent_encoding::{NON_ALPHANUMERIC, percent_encode};
+use reqwest::header::HeaderMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Tokens expiring within this window fail the `token_expiry` check.
+const TOKEN_EXPIRY_WARNING_SECS: u64 = 7 * 24 * 60 * 60;
+
+pub async fn preflight(keyhouse_config: &KeyhouseConf) -> Result<PreflightReport> {
+    let mut report = PreflightReport::default();
+
+    let config_ok = !keyhouse_config.base_url.trim().is_empty()
+        && (!keyhouse_config.token.is_blank() || keyhouse_config.github_app.is_some());
+    report.record(
+        "config",
+        config_ok,
+        if config_ok {
+            format!("base_url {}", keyhouse_config.base_url)
+        } else {
+            "base_url and token (or github_app) must both be set".to_string()
+        },
+    );
+
+    let client = GitHubClient::new(keyhouse_config)?;
+    if let Err(e) = client.refresh_auth().await {
+        report.record("github_app", false, e.to_string());
+    }
+    match send_with_ratelimit(&client, client.get(client.repo_url())).await {
+        Ok(response) if response.status().is_success() => {
+            report.record(
+                "github_auth",
+                true,
+                format!("{} returned {}", client.repo_url(), response.status()),
+            );
+            check_token_headers(&mut report, response.headers());
+        }
+        Ok(response) => report.record(
+            "github_auth",
+            false,
+            error_from_response(response).await.to_string(),
+        ),
+        Err(e) => report.record("github_auth", false, e.to_string()),
+    }
+
+    let branch_url = format!(
+        "{}/branches/{}",
+        client.repo_url(),
+        percent_encode(client.branch.as_bytes(), NON_ALPHANUMERIC)
+    );
+    match send_with_ratelimit(&client, client.get(&branch_url)).await {
+        Ok(response) if response.status().is_success() => report.record(
+            "branch",
+            true,
+            format!("branch '{}' returned {}", client.branch, response.status()),
+        ),
+        Ok(response) => report.record(
+            "branch",
+            false,
+            error_from_response(response).await.to_string(),
+        ),
+        Err(e) => report.record("branch", false, e.to_string()),
+    }
+
+    set_privilege_command(keyhouse_config.privilege_command());
+    match (can_escalate(), keyhouse_config.privilege_command()) {
+        (Ok(true), Some(command)) => report.record("sudo", true, format!("{} -n works", command)),
+        (Ok(true), None) => report.record("sudo", true, "running as root".to_string()),
+        (Ok(false), Some(command)) => report.record(
+            "sudo",
+            false,
+            format!("{} -n requires a password or is not permitted", command),
+        ),
+        (Ok(false), None) => report.record(
+            "sudo",
+            false,
+            "privilege_command is 'none' but the process is not root".to_string(),
+        ),
+        (Err(e), _) => report.record("sudo", false, e.to_string()),
+    }
+
+    for check in &report.checks {
+        if check.passed {
+            info!(target:get_log_target(), commit = log_commit(); "Preflight {}: ok ({})", check.name, check.detail);
+        } else {
+            warn!(target:get_log_target(), commit = log_commit(); "Preflight {}: FAILED ({})", check.name, check.detail);
+        }
+    }
+    Ok(report)
+}
+
+// Classic PATs report their scopes and expiring tokens report their expiry;
+// fine-grained PATs omit the scopes header, so that check is skipped for them.
+fn check_token_headers(report: &mut PreflightReport, headers: &HeaderMap) {
+    if let Some(scopes) = headers
+        .get(OAUTH_SCOPES_HEADER)
+        .and_then(|value| value.to_str().ok())
+    {
+        let scopes_ok = has_read_scope(scopes);
+        report.record(
+            "token_scopes",
+            scopes_ok,
+            if scopes_ok {
+                format!("scopes [{}]", scopes)
+            } else {
+                format!(
+                    "scopes [{}] lack 'repo' or 'public_repo' for reading contents",
+                    scopes
+                )
+            },
+        );
+    }
+
+    let Some(expiration) = headers
+        .get(TOKEN_EXPIRATION_HEADER)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return;
+    };
+    let Some(expires_at) = parse_token_expiration(expiration) else {
+        report.record(
+            "token_expiry",
+            false,
+            format!("unrecognised expiration '{}'", expiration),
+        );
+        return;
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    let remaining = expires_at.saturating_sub(now);
+    if remaining == 0 {
+        report.record(
+            "token_expiry",
+            false,
+            format!("token expired at {}", expiration),
+        );
+    } else if remaining < TOKEN_EXPIRY_WARNING_SECS {
+        report.record(
+            "token_expiry",
+            false,
+            format!("token expires at {} (in {}h)", expiration, remaining / 3600),
+        );
+    } else {
+        report.record(
+            "token_expiry",
+            true,
+            format!("token expires at {}", expiration),
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use crate::config::{MatchStrategy, get_log_target, log_commit};
+use log::{debug, error};
+use regex::Regex;
+
+pub fn provider_matches(provider: &str, hostname: &str, strategy: &MatchStrategy) -> bool {
+    match strategy {
+        MatchStrategy::Exact => provider == hostname,
+        MatchStrategy::Prefix => hostname.starts_with(provider),
+        MatchStrategy::Regex(pattern) => match Regex::new(pattern) {
+            Ok(re) => re
+                .captures(hostname)
+                .and_then(|caps| caps.get(1))
+                .is_some_and(|host_provider| host_provider.as_str() == provider),
+            Err(e) => {
+                error!(target:get_log_target(), commit = log_commit(); "Invalid provider_match regex {:?}: {}", pattern, e);
+                false
+            }
+        },
+    }
+}
+
+/// `provider_matches`, additionally restricted to the locally configured
+/// `allowed_providers` when set, so a matching bug can't widen what a host
+/// acts on.
+pub fn provider_allowed(
+    provider: &str,
+    hostname: &str,
+    strategy: &MatchStrategy,
+    allowed_providers: Option<&[String]>,
+) -> bool {
+    if let Some(allowed) = allowed_providers
+        && !allowed.iter().any(|allowed| allowed == provider)
+    {
+        debug!(target:get_log_target(), commit = log_commit(); "Provider {} is not in allowed_providers, skipping", provider);
+        return false;
+    }
+    provider_matches(provider, hostname, strategy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_needs_the_whole_hostname() {
+        assert!(provider_matches("aws-1", "aws-1", &MatchStrategy::Exact));
+        assert!(!provider_matches("aws", "aws-1", &MatchStrategy::Exact));
+    }
+
+    #[test]
+    fn prefix_matches_the_start_of_the_hostname() {
+        assert!(provider_matches("aws", "aws-web-3", &MatchStrategy::Prefix));
+        assert!(!provider_matches(
+            "gcp",
+            "aws-web-3",
+            &MatchStrategy::Prefix
+        ));
+    }
+
+    #[test]
+    fn regex_compares_the_first_capture() {
+        let strategy = MatchStrategy::Regex(r"^([a-z]+)-\d+$".to_string());
+        assert!(provider_matches("aws", "aws-12", &strategy));
+        assert!(!provider_matches("aws", "gcp-12", &strategy));
+        assert!(!provider_matches("aws", "aws-web", &strategy));
+    }
+
+    #[test]
+    fn invalid_regex_matches_nothing() {
+        let strategy = MatchStrategy::Regex("(".to_string());
+        assert!(!provider_matches("aws", "aws", &strategy));
+    }
+
+    #[test]
+    fn allowlist_narrows_but_never_widens_the_match() {
+        let allowed = ["aws".to_string()];
+        let allowed = Some(&allowed[..]);
+        assert!(provider_allowed(
+            "aws",
+            "aws-web-3",
+            &MatchStrategy::Prefix,
+            allowed
+        ));
+        assert!(!provider_allowed(
+            "aws-web",
+            "aws-web-3",
+            &MatchStrategy::Prefix,
+            allowed
+        ));
+        assert!(!provider_allowed(
+            "aws",
+            "gcp-web-3",
+            &MatchStrategy::Prefix,
+            allowed
+        ));
+        assert!(provider_allowed(
+            "aws-web",
+            "aws-web-3",
+            &MatchStrategy::Prefix,
+            None
+        ));
+    }
+}
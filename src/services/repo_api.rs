@@ -0,0 +1,113 @@
+use crate::error::{Result, WatchdogError};
+
+/// The repository's REST endpoints, derived once from the configured
+/// `base_url`. Accepts `https://api.github.com/repos/o/r` with or without a
+/// trailing `/contents` and with or without trailing slashes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepoApi {
+    api_root: String,
+    repo_url: String,
+}
+
+impl RepoApi {
+    pub fn parse(base_url: &str) -> Result<Self> {
+        let trimmed = base_url.trim().trim_end_matches('/');
+        let repo_url = trimmed
+            .strip_suffix("/contents")
+            .unwrap_or(trimmed)
+            .trim_end_matches('/');
+        let invalid = || {
+            WatchdogError::Config(format!(
+                "base_url '{}' is not a GitHub repository API URL (.../repos/<owner>/<repo>)",
+                base_url
+            ))
+        };
+        let idx = repo_url.rfind("/repos/").ok_or_else(invalid)?;
+        let owner_and_repo: Vec<&str> = repo_url[idx + "/repos/".len()..].split('/').collect();
+        if owner_and_repo.len() != 2 || owner_and_repo.iter().any(|part| part.is_empty()) {
+            return Err(invalid());
+        }
+        Ok(RepoApi {
+            api_root: repo_url[..idx].to_string(),
+            repo_url: repo_url.to_string(),
+        })
+    }
+
+    /// The API root, e.g. `https://api.github.com`.
+    pub fn api_root(&self) -> &str {
+        &self.api_root
+    }
+
+    /// `{api_root}/repos/{owner}/{repo}`.
+    pub fn repo_url(&self) -> &str {
+        &self.repo_url
+    }
+
+    pub fn contents_url(&self) -> String {
+        format!("{}/contents", self.repo_url)
+    }
+
+    pub fn commits_url(&self) -> String {
+        format!("{}/commits", self.repo_url)
+    }
+
+    pub fn compare_url(&self, base: &str, head: &str) -> String {
+        format!("{}/compare/{}...{}", self.repo_url, base, head)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contents_suffix_and_trailing_slashes_are_normalized() {
+        for base_url in [
+            "https://api.github.com/repos/octo/keyhouse",
+            "https://api.github.com/repos/octo/keyhouse/",
+            "https://api.github.com/repos/octo/keyhouse/contents",
+            "https://api.github.com/repos/octo/keyhouse/contents/",
+            " https://api.github.com/repos/octo/keyhouse/contents// ",
+        ] {
+            let repo = RepoApi::parse(base_url).unwrap();
+            assert_eq!(repo.api_root(), "https://api.github.com", "{:?}", base_url);
+            assert_eq!(
+                repo.repo_url(),
+                "https://api.github.com/repos/octo/keyhouse",
+                "{:?}",
+                base_url
+            );
+        }
+    }
+
+    #[test]
+    fn builds_endpoint_urls() {
+        let repo =
+            RepoApi::parse("https://ghe.example.com/api/v3/repos/octo/keyhouse/contents").unwrap();
+        assert_eq!(repo.api_root(), "https://ghe.example.com/api/v3");
+        assert_eq!(
+            repo.contents_url(),
+            "https://ghe.example.com/api/v3/repos/octo/keyhouse/contents"
+        );
+        assert_eq!(
+            repo.commits_url(),
+            "https://ghe.example.com/api/v3/repos/octo/keyhouse/commits"
+        );
+        assert_eq!(
+            repo.compare_url("aaa", "bbb"),
+            "https://ghe.example.com/api/v3/repos/octo/keyhouse/compare/aaa...bbb"
+        );
+    }
+
+    #[test]
+    fn rejects_urls_that_are_not_a_repo() {
+        for base_url in [
+            "https://api.github.com/octo/keyhouse",
+            "https://api.github.com/repos/octo",
+            "https://api.github.com/repos/octo/keyhouse/extra",
+            "https://api.github.com/repos//keyhouse",
+        ] {
+            assert!(RepoApi::parse(base_url).is_err(), "{:?}", base_url);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use crate::error::{Result, WatchdogError};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+
+// The keyhouse repo keeps grants at `access/<provider>/<project>/<hash>` and
+// identities at `names/<hash>`; this module is the only place that knows it.
+pub const ACCESS_DIR: &str = "access";
+pub const NAMES_DIR: &str = "names";
+/// Reserved file name inside a project directory; never a user hash.
+pub const PROJECT_META_FILE: &str = "meta.json";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessPath {
+    pub provider: String,
+    pub project: String,
+    pub hash: String,
+}
+
+pub fn is_safe_path_segment(segment: &str) -> bool {
+    !segment.is_empty()
+        && segment != "."
+        && segment != ".."
+        && segment
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+// Segments come from repo-controlled diffs and listings, so anything that
+// could escape its directory is refused before it reaches a URL.
+pub fn repo_path(segments: &[&str]) -> Result<String> {
+    let mut encoded = Vec::with_capacity(segments.len());
+    for segment in segments {
+        if !is_safe_path_segment(segment) {
+            return Err(WatchdogError::GitHub(format!(
+                "Refusing unsafe repo path segment {:?}",
+                segment
+            )));
+        }
+        encoded.push(utf8_percent_encode(segment, PATH_SEGMENT).to_string());
+    }
+    Ok(encoded.join("/"))
+}
+
+pub fn names_path(hash: &str) -> Result<String> {
+    repo_path(&[NAMES_DIR, hash])
+}
+
+pub fn access_path(provider: &str, project: &str, hash: &str) -> Result<String> {
+    repo_path(&[ACCESS_DIR, provider, project, hash])
+}
+
+pub fn project_meta_path(provider: &str, project: &str) -> Result<String> {
+    repo_path(&[ACCESS_DIR, provider, project, PROJECT_META_FILE])
+}
+
+fn safe_segments(path: &str) -> Option<Vec<&str>> {
+    let segments: Vec<&str> = path.split('/').collect();
+    segments
+        .iter()
+        .all(|segment| is_safe_path_segment(segment))
+        .then_some(segments)
+}
+
+pub fn parse_access_path(path: &str) -> Option<AccessPath> {
+    match safe_segments(path)?.as_slice() {
+        [ACCESS_DIR, provider, project, hash] => Some(AccessPath {
+            provider: provider.to_string(),
+            project: project.to_string(),
+            hash: hash.to_string(),
+        }),
+        _ => None,
+    }
+}
+
+pub fn parse_names_path(path: &str) -> Option<String> {
+    match safe_segments(path)?.as_slice() {
+        [NAMES_DIR, hash] => Some(hash.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_paths_from_safe_segments() {
+        assert_eq!(names_path("ab12cd.pub").unwrap(), "names/ab12cd.pub");
+        assert_eq!(
+            access_path("aws", "my.project", "ab12cd").unwrap(),
+            "access/aws/my.project/ab12cd"
+        );
+    }
+
+    #[test]
+    fn refuses_malicious_hashes() {
+        for hash in [
+            "../../secret",
+            "..",
+            ".",
+            "",
+            "a/b",
+            "abc?ref=evil",
+            "abc#frag",
+            "abc%2F..",
+            "abc\n",
+        ] {
+            assert!(names_path(hash).is_err(), "{:?}", hash);
+            assert!(access_path("aws", "proj", hash).is_err(), "{:?}", hash);
+        }
+    }
+
+    #[test]
+    fn parsing_rejects_traversal() {
+        assert_eq!(parse_names_path("names/../access"), None);
+        assert_eq!(parse_access_path("access/aws/../abc"), None);
+        assert_eq!(
+            parse_access_path("access/aws/proj/abc"),
+            Some(AccessPath {
+                provider: "aws".to_string(),
+                project: "proj".to_string(),
+                hash: "abc".to_string(),
+            })
+        );
+    }
+}
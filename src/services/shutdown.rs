@@ -0,0 +1,43 @@
+use crate::config::{get_log_target, log_commit};
+use log::{error, info};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::Notify;
+
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+static SHUTDOWN_NOTIFY: Notify = Notify::const_new();
+
+pub fn request_shutdown() {
+    SHUTDOWN_REQUESTED.store(true, Ordering::SeqCst);
+    SHUTDOWN_NOTIFY.notify_waiters();
+}
+
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN_REQUESTED.load(Ordering::SeqCst)
+}
+
+pub async fn wait_for_shutdown() {
+    let notified = SHUTDOWN_NOTIFY.notified();
+    if shutdown_requested() {
+        return;
+    }
+    notified.await;
+}
+
+pub fn install_signal_handlers() {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            error!(target:get_log_target(), commit = log_commit(); "Failed to install SIGTERM handler: {}", e);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = terminate.recv() => {}
+            _ = tokio::signal::ctrl_c() => {}
+        }
+        info!(target:get_log_target(), commit = log_commit(); "Shutdown signal received, shutting down after current cycle");
+        request_shutdown();
+    });
+}
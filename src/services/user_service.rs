@@ -1,101 +1,369 @@
-use crate::config::get_log_target;
-use log::{error, info};
+use crate::config::{DeletionPolicy, get_log_target, get_user_conf, log_commit};
+use crate::models::group_add_outcome::GroupAddOutcome;
+use crate::models::reconcile_result::ReconcileResult;
+use crate::services::command_runner::{
+    ensure_privileged, privilege_command, run_as_user, run_command, run_privileged,
+};
+use log::{error, info, warn};
+use nix::unistd::{Group, User, getgrouplist};
+use regex::Regex;
+use std::ffi::CString;
 use std::fs;
-use std::fs::OpenOptions;
 use std::io;
 use std::io::Result;
-use std::io::Write;
-use std::process::Command;
+use std::process::Output;
+use std::sync::LazyLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DISABLED_MARKER: &str = ".watchdog-disabled";
+const NOLOGIN_SHELL: &str = "/usr/sbin/nologin";
+/// Restored on re-enable when neither the marker nor `default_shell` names one.
+const DEFAULT_SHELL: &str = "/bin/bash";
+
+pub fn home_dir(user: &str) -> String {
+    format!(
+        "{}/{}",
+        get_user_conf().home_base.trim_end_matches('/'),
+        user
+    )
+}
+
+/// Names useradd and groupadd accept without `--badname`.
+static NAME_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[a-z_][a-z0-9_-]{0,31}$").unwrap());
+
+/// `kind` names what is checked in the log and error, e.g. "group name".
+fn validate_name(kind: &str, name: &str) -> io::Result<()> {
+    if NAME_RE.is_match(name) {
+        return Ok(());
+    }
+    error!(target:get_log_target(), commit = log_commit(); "Refusing invalid {} {:?}", kind, name);
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("Invalid {} {:?}", kind, name),
+    ))
+}
+
+pub fn validate_username(name: &str) -> io::Result<()> {
+    validate_name("username", name)
+}
+
+/// Break-glass accounts in `protected_users` are never touched, whatever the
+/// repo says.
+pub fn is_protected(user: &str, action: &str) -> bool {
+    let protected = get_user_conf()
+        .protected_users
+        .iter()
+        .any(|protected| protected == user);
+    if protected {
+        warn!(target:get_log_target(), commit = log_commit(), user = user; "Refusing to {} protected user '{}'.", action, user);
+    }
+    protected
+}
 
 pub fn user_exists(username: &str) -> io::Result<bool> {
-    let output = Command::new("id").arg(username).output()?;
-    Ok(output.status.success())
+    Ok(User::from_name(username)?.is_some())
 }
 
 pub fn group_exists(group: &str) -> bool {
-    fs::read_to_string("/etc/group")
-        .map(|contents| {
-            contents
-                .lines()
-                .any(|line| line.starts_with(&format!("{}:", group)))
-        })
+    Group::from_name(group)
+        .map(|entry| entry.is_some())
         .unwrap_or(false)
 }
 
-pub fn create_user(user: &str) -> io::Result<()> {
-    let home_dir = format!("/opt/watchdog/users/{}", user);
-
-    let output = Command::new("sudo")
-        .arg("useradd")
-        .arg("-m")
-        .arg("-d")
-        .arg(&home_dir)
-        .arg("--skel")
-        .arg("/etc/skel")
-        .arg(user)
-        .output()?;
+pub fn local_group_name(group: &str) -> String {
+    let user_conf = get_user_conf();
+    match user_conf.group_aliases.get(group) {
+        Some(alias) => alias.clone(),
+        None if user_conf.sudo_wheel_fallback && group == "sudo" => "wheel".to_string(),
+        None => group.to_string(),
+    }
+}
 
-    if !output.status.success() {
-        error!(target:get_log_target(),
-            "Failed to create user '{}': {}",
-            user,
+// An explicit alias wins when the local group exists; otherwise the repo name
+// is used as-is. The implicit sudo -> wheel fallback only applies when there
+// is no sudo group, so hosts with both keep granting sudo.
+pub fn resolve_local_group(group: &str) -> Option<String> {
+    let local = local_group_name(group);
+    let explicit = get_user_conf().group_aliases.contains_key(group);
+    let candidates = if explicit {
+        [local, group.to_string()]
+    } else {
+        [group.to_string(), local]
+    };
+    candidates
+        .into_iter()
+        .find(|candidate| group_exists(candidate))
+}
+
+pub fn create_group(group: &str) -> io::Result<()> {
+    validate_name("group name", group)?;
+    if get_user_conf().dry_run {
+        info!(target:get_log_target(), commit = log_commit(); "[dry-run] Would create group '{}'.", group);
+        return Ok(());
+    }
+
+    let output = run_privileged(&["groupadd", group])?;
+
+    if output.status.success() {
+        info!(target:get_log_target(), commit = log_commit(); "Group '{}' created.", group);
+        Ok(())
+    } else {
+        error!(target:get_log_target(), commit = log_commit();
+            "Failed to create group '{}': {}",
+            group,
             String::from_utf8_lossy(&output.stderr)
         );
+        Err(io::Error::other("Failed to create group"))
+    }
+}
+
+pub fn can_escalate() -> io::Result<bool> {
+    match privilege_command() {
+        Some(command) => Ok(run_command(&command, &["-n", "true"])?.status.success()),
+        None => Ok(ensure_privileged().is_ok()),
+    }
+}
+
+pub fn useradd_args(user: &str, home_dir: &str) -> Vec<String> {
+    let user_conf = get_user_conf();
+    let mut args: Vec<String> = ["useradd", "-m", "-d", home_dir, "--skel", "/etc/skel"]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+    if let Some(shell) = &user_conf.default_shell {
+        args.push("-s".to_string());
+        args.push(shell.clone());
+    }
+    if let Some(group) = &user_conf.primary_group {
+        args.push("-g".to_string());
+        args.push(group.clone());
+    }
+    args.extend(user_conf.extra_useradd_args.iter().cloned());
+    args.push(user.to_string());
+    args
+}
+
+pub fn create_user(user: &str) -> io::Result<()> {
+    validate_username(user)?;
+    if is_protected(user, "create") {
+        return Ok(());
+    }
+    let home_dir = home_dir(user);
+    if get_user_conf().dry_run {
+        info!(target:get_log_target(), commit = log_commit(); "[dry-run] Would create user '{}' with home '{}'.", user, home_dir);
+        return Ok(());
+    }
+
+    let args = useradd_args(user, &home_dir);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let output = run_privileged(&args)?;
+
+    // useradd exits with 9 when the name is taken, e.g. by an overlapping sync,
+    // but also when a group of that name exists; only a user that is really
+    // there now counts as created.
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success()
+        && (output.status.code() == Some(9) || stderr.contains("already exists"))
+        && user_exists(user)?
+    {
+        info!(target:get_log_target(), commit = log_commit(); "User '{}' already exists, continuing.", user);
+        return Ok(());
+    }
+    if !output.status.success() {
+        error!(target:get_log_target(), commit = log_commit(); "Failed to create user '{}': {}", user, stderr);
         return Err(io::Error::other("Failed to create user"));
     }
 
     match update_user_bashrc(user) {
         Ok(_) => {
-            info!(target:get_log_target(),"User {} bashrc updated successfully.", user);
+            info!(target:get_log_target(), commit = log_commit();"User {} bashrc updated successfully.", user);
         }
         Err(e) => {
-            error!(target:get_log_target(), "Failed to update user {} bashrc: {}", user, e);
+            error!(target:get_log_target(), commit = log_commit(); "Failed to update user {} bashrc: {}", user, e);
         }
     }
+    if let Err(e) = fix_home_ownership(user) {
+        error!(target:get_log_target(), commit = log_commit(); "Failed to fix home ownership for {}: {}", user, e);
+    }
 
     Ok(())
 }
 
-pub fn add_user_to_group(user: &str, group: &str) -> io::Result<()> {
-    if !user_exists(user)? {
-        info!(target:get_log_target(), "User '{}' does not exist. Creating user...", user);
-        create_user(user)?;
+/// Hands the home back to the user (the skeleton may be root-owned) and sets
+/// home 0750, .bashrc 0644, .ssh 0700.
+pub fn fix_home_ownership(user: &str) -> io::Result<()> {
+    validate_username(user)?;
+    let home_dir = home_dir(user);
+    if get_user_conf().dry_run {
+        info!(target:get_log_target(), commit = log_commit(); "[dry-run] Would fix ownership of '{}'.", home_dir);
+        return Ok(());
     }
+    chown_to_user(user, &home_dir)?;
+    let output = run_as_user(
+        user,
+        &[
+            "sh",
+            "-c",
+            r#"chmod 750 "$1" && { [ ! -f "$1/.bashrc" ] || chmod 644 "$1/.bashrc"; } && { [ ! -d "$1/.ssh" ] || chmod 700 "$1/.ssh"; }"#,
+            "sh",
+            &home_dir,
+        ],
+    )?;
+    check_home_command(user, "set permissions in", &home_dir, output)
+}
 
-    let group_to_add = if group == "sudo" {
-        if group_exists("sudo") {
-            "sudo"
-        } else if group_exists("wheel") {
-            "wheel"
-        } else {
-            error!(target:get_log_target(), "Neither 'sudo' nor 'wheel' group exists.");
+fn local_group_for_add(group: &str) -> io::Result<String> {
+    let local = match resolve_local_group(group) {
+        Some(local) => local,
+        None if get_user_conf().auto_create_groups => local_group_name(group),
+        None => {
+            error!(target:get_log_target(), commit = log_commit();
+                "Group '{}' (local name '{}') does not exist.",
+                group,
+                local_group_name(group)
+            );
             return Err(io::Error::new(
                 io::ErrorKind::NotFound,
-                "No admin group ('sudo' or 'wheel') found",
+                format!("Group '{}' not found", group),
             ));
         }
-    } else if group_exists(group) {
-        group
-    } else {
-        error!(target:get_log_target(), "Group '{}' does not exist.", group);
+    };
+    // Checked on the resolved name so an alias (sudo -> wheel) can't be used
+    // to reach a forbidden group.
+    if get_user_conf().forbidden_groups.contains(&local) {
+        error!(target:get_log_target(), commit = log_commit(); "Refusing grant of forbidden group '{}' (from '{}').", local, group);
         return Err(io::Error::new(
-            io::ErrorKind::NotFound,
-            format!("Group '{}' not found", group),
+            io::ErrorKind::PermissionDenied,
+            format!("Group '{}' is forbidden", local),
         ));
+    }
+    if !group_exists(&local) {
+        create_group(&local)?;
+    }
+    Ok(local)
+}
+
+pub fn group_list_arg(groups: &[String]) -> String {
+    let mut unique: Vec<&str> = Vec::new();
+    for group in groups {
+        if !unique.contains(&group.as_str()) {
+            unique.push(group);
+        }
+    }
+    unique.join(",")
+}
+
+// Creates the user if needed and returns whether it was created along with the
+// groups it already belongs to (empty for a user that only exists in a dry run).
+fn ensure_user_for_add(user: &str) -> io::Result<(bool, Vec<String>)> {
+    let created = !user_exists(user)?;
+    if created {
+        info!(target:get_log_target(), commit = log_commit(); "User '{}' does not exist. Creating user...", user);
+        create_user(user)?;
+    } else if is_disabled(user)? {
+        info!(target:get_log_target(), commit = log_commit(); "User '{}' was disabled and is granted access again.", user);
+        enable_user(user)?;
+    }
+    let current = if user_exists(user)? {
+        current_groups(user)?
+    } else {
+        Vec::new()
     };
+    Ok((created, current))
+}
+
+fn apply_primary_group(user: &str, groups: &[String]) -> io::Result<()> {
+    let primary_project = groups
+        .iter()
+        .find(|group| get_user_conf().primary_group_projects.contains(group));
+    match primary_project {
+        Some(group) => set_primary_group(user, group),
+        None => Ok(()),
+    }
+}
+
+/// Adds the user to every group with one `usermod` call; the outcomes are in
+/// the order of `groups`.
+pub fn add_user_to_groups(user: &str, groups: &[String]) -> io::Result<Vec<GroupAddOutcome>> {
+    validate_username(user)?;
+    if is_protected(user, "add to groups") {
+        return Ok(vec![GroupAddOutcome::Skipped; groups.len()]);
+    }
+    if groups.is_empty() {
+        return Ok(Vec::new());
+    }
+    let (created, current) = ensure_user_for_add(user)?;
+
+    let local_groups = groups
+        .iter()
+        .map(|group| local_group_for_add(group))
+        .collect::<io::Result<Vec<_>>>()?;
+    let outcomes: Vec<GroupAddOutcome> = local_groups
+        .iter()
+        .map(|group| GroupAddOutcome::new(created, !current.contains(group)))
+        .collect();
+    let missing: Vec<String> = local_groups
+        .iter()
+        .filter(|group| !current.contains(group))
+        .cloned()
+        .collect();
+    if missing.is_empty() {
+        info!(target:get_log_target(), commit = log_commit(); "User '{}' is already in all of '{}'.", user, group_list_arg(&local_groups));
+        apply_primary_group(user, groups)?;
+        return Ok(outcomes);
+    }
+    let group_list = group_list_arg(&missing);
+    if get_user_conf().dry_run {
+        info!(target:get_log_target(), commit = log_commit(); "[dry-run] Would add user '{}' to groups '{}'.", user, group_list);
+        return Ok(outcomes);
+    }
 
-    let output = Command::new("sudo")
-        .arg("usermod")
-        .arg("-aG")
-        .arg(group_to_add)
-        .arg(user)
-        .output()?;
+    let output = run_privileged(&["usermod", "-aG", &group_list, user])?;
 
     if output.status.success() {
-        info!(target:get_log_target(), "User '{}' added to group '{}'.", user, group_to_add);
-        Ok(())
+        info!(target:get_log_target(), commit = log_commit(), user = user, group = group_list.as_str(); "User '{}' added to groups '{}'.", user, group_list);
+        apply_primary_group(user, groups)?;
+        Ok(outcomes)
+    } else {
+        error!(target:get_log_target(), commit = log_commit();
+            "Failed to add user '{}' to groups '{}': {}",
+            user,
+            group_list,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        Err(io::Error::other("Failed to add user to groups"))
+    }
+}
+
+pub fn add_user_to_group(user: &str, group: &str) -> io::Result<GroupAddOutcome> {
+    validate_username(user)?;
+    if is_protected(user, "add to a group") {
+        return Ok(GroupAddOutcome::Skipped);
+    }
+    let (created, current) = ensure_user_for_add(user)?;
+
+    let group_to_add = local_group_for_add(group)?;
+    let group_to_add = group_to_add.as_str();
+    let outcome = GroupAddOutcome::new(created, !current.iter().any(|g| g == group_to_add));
+    if !outcome.group_added() {
+        info!(target:get_log_target(), commit = log_commit(); "User '{}' is already in group '{}'.", user, group_to_add);
+        apply_primary_group(user, &[group.to_string()])?;
+        return Ok(outcome);
+    }
+    if get_user_conf().dry_run {
+        info!(target:get_log_target(), commit = log_commit(); "[dry-run] Would add user '{}' to group '{}'.", user, group_to_add);
+        return Ok(outcome);
+    }
+
+    let output = run_privileged(&["usermod", "-aG", group_to_add, user])?;
+
+    if output.status.success() {
+        info!(target:get_log_target(), commit = log_commit(), user = user, group = group_to_add; "User '{}' added to group '{}'.", user, group_to_add);
+        apply_primary_group(user, &[group.to_string()])?;
+        Ok(outcome)
     } else {
-        error!(target:get_log_target(),
+        error!(target:get_log_target(), commit = log_commit(), user = user, group = group_to_add;
             "Failed to add user '{}' to group '{}': {}",
             user,
             group_to_add,
@@ -105,40 +373,244 @@ pub fn add_user_to_group(user: &str, group: &str) -> io::Result<()> {
     }
 }
 
+/// Makes `group` the user's primary group (`usermod -g`), for projects listed
+/// in `primary_group_projects`.
+pub fn set_primary_group(user: &str, group: &str) -> io::Result<()> {
+    validate_username(user)?;
+    if is_protected(user, "change the primary group of") {
+        return Ok(());
+    }
+    let Some(local_group) = resolve_local_group(group) else {
+        error!(target:get_log_target(), commit = log_commit(); "Cannot set primary group of '{}': group '{}' does not exist", user, group);
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Group '{}' does not exist", group),
+        ));
+    };
+    let local_group = local_group.as_str();
+    if get_user_conf().dry_run {
+        info!(target:get_log_target(), commit = log_commit(); "[dry-run] Would set primary group of '{}' to '{}'.", user, local_group);
+        return Ok(());
+    }
+    let output = run_privileged(&["usermod", "-g", local_group, user])?;
+
+    if output.status.success() {
+        info!(target:get_log_target(), commit = log_commit(), user = user, group = local_group; "Primary group of '{}' set to '{}'.", user, local_group);
+        Ok(())
+    } else {
+        error!(target:get_log_target(), commit = log_commit(), user = user, group = local_group;
+            "Failed to set primary group of '{}' to '{}': {}",
+            user,
+            local_group,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        Err(io::Error::other("Failed to set primary group"))
+    }
+}
+
+/// Sets the user's login shell (`usermod -s`) unless it already matches.
+pub fn set_login_shell(user: &str, shell: &str) -> io::Result<()> {
+    validate_username(user)?;
+    if is_protected(user, "change the shell of") {
+        return Ok(());
+    }
+    if let Some(user_entry) = User::from_name(user)?
+        && user_entry.shell.as_os_str() == shell
+    {
+        return Ok(());
+    }
+    if get_user_conf().dry_run {
+        info!(target:get_log_target(), commit = log_commit(); "[dry-run] Would set shell of '{}' to '{}'.", user, shell);
+        return Ok(());
+    }
+    let output = run_privileged(&["usermod", "-s", shell, user])?;
+
+    if output.status.success() {
+        info!(target:get_log_target(), commit = log_commit(), user = user; "Shell of '{}' set to '{}'.", user, shell);
+        Ok(())
+    } else {
+        error!(target:get_log_target(), commit = log_commit(), user = user;
+            "Failed to set shell of '{}' to '{}': {}",
+            user,
+            shell,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        Err(io::Error::other("Failed to set login shell"))
+    }
+}
+
+fn is_primary_group(user: &str, group: &str) -> io::Result<bool> {
+    let Some(user_entry) = User::from_name(user)? else {
+        return Ok(false);
+    };
+    Ok(Group::from_name(group)?.is_some_and(|entry| entry.gid == user_entry.gid))
+}
+
 pub fn remove_user_from_group(user: &str, group: &str) -> io::Result<()> {
-    let output = Command::new("sudo")
-        .arg("gpasswd")
-        .arg("-d")
-        .arg(user)
-        .arg(group)
-        .output()?;
+    validate_username(user)?;
+    if is_protected(user, "remove from a group") {
+        return Ok(());
+    }
+    let group = resolve_local_group(group).unwrap_or_else(|| local_group_name(group));
+    let group = group.as_str();
+    // gpasswd -d never touches the primary group, and reports it as "not a
+    // member", which would otherwise be taken as a successful removal.
+    if is_primary_group(user, group)? {
+        error!(target:get_log_target(), commit = log_commit(), user = user, group = group;
+            "Cannot remove user '{}' from '{}': it is their primary group, set another with set_primary_group first",
+            user,
+            group
+        );
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("'{}' is the primary group of user '{}'", group, user),
+        ));
+    }
+    if get_user_conf().dry_run {
+        info!(target:get_log_target(), commit = log_commit(); "[dry-run] Would remove user '{}' from group '{}'.", user, group);
+        return Ok(());
+    }
+    let output = run_privileged(&["gpasswd", "-d", user, group])?;
 
+    // gpasswd fails with "user 'x' is not a member of 'g'" when there is
+    // nothing to remove, which reconciliation hits routinely.
+    let stderr = String::from_utf8_lossy(&output.stderr);
     if output.status.success() {
-        info!(target:get_log_target(), "User '{}' removed from group '{}'.", user, group);
+        info!(target:get_log_target(), commit = log_commit(), user = user, group = group; "User '{}' removed from group '{}'.", user, group);
+        Ok(())
+    } else if stderr.contains("is not a member of") {
+        info!(target:get_log_target(), commit = log_commit(), user = user, group = group; "User '{}' is not in group '{}', nothing to remove.", user, group);
         Ok(())
     } else {
-        error!(target:get_log_target(),
+        error!(target:get_log_target(), commit = log_commit(), user = user, group = group;
             "Failed to remove user '{}' from group '{}': {}",
             user,
             group,
-            String::from_utf8_lossy(&output.stderr)
+            stderr
         );
         Err(io::Error::other("Failed to remove user from group"))
     }
 }
 
+pub fn remove_user_from_all_groups(user: &str, managed_groups: &[String]) -> io::Result<()> {
+    validate_username(user)?;
+    let mut failed_groups = Vec::new();
+    for group in managed_groups {
+        if let Err(e) = remove_user_from_group(user, group) {
+            error!(target:get_log_target(), commit = log_commit(); "Failed to remove user '{}' from group '{}': {}", user, group, e);
+            failed_groups.push(group.as_str());
+        }
+    }
+
+    if failed_groups.is_empty() {
+        info!(target:get_log_target(), commit = log_commit(); "User '{}' removed from all managed groups.", user);
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "Failed to remove user '{}' from groups: {}",
+            user,
+            failed_groups.join(", ")
+        )))
+    }
+}
+
+pub fn current_groups(user: &str) -> io::Result<Vec<String>> {
+    validate_username(user)?;
+    let Some(entry) = User::from_name(user)? else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("User '{}' not found", user),
+        ));
+    };
+    let mut groups = Vec::new();
+    for gid in getgrouplist(&CString::new(user)?, entry.gid)? {
+        if let Some(group) = Group::from_gid(gid)? {
+            groups.push(group.name);
+        }
+    }
+    Ok(groups)
+}
+
+// Only groups in `managed` are touched, so system groups the repo doesn't
+// know about (docker, adm, ...) survive a reconcile.
+pub fn reconcile_user_groups(
+    user: &str,
+    desired: &[String],
+    managed: &[String],
+) -> io::Result<ReconcileResult> {
+    let current = current_groups(user)?;
+    // Compared on the local names the host actually uses, but add/remove get
+    // the repo names, since they resolve aliases themselves.
+    let local = |group: &str| resolve_local_group(group).unwrap_or_else(|| local_group_name(group));
+    let desired: Vec<&String> = desired
+        .iter()
+        .filter(|group| managed.contains(group))
+        .collect();
+    let desired_local: Vec<String> = desired.iter().map(|group| local(group)).collect();
+    let mut result = ReconcileResult::default();
+    for (group, local_name) in desired.iter().zip(&desired_local) {
+        if current.contains(local_name) || result.added.contains(local_name) {
+            continue;
+        }
+        match add_user_to_group(user, group) {
+            Ok(outcome) if outcome.group_added() => result.added.push(local_name.clone()),
+            Ok(_) => {}
+            Err(e) => {
+                error!(target:get_log_target(), commit = log_commit(); "Reconcile failed to add '{}' to '{}': {}", user, group, e);
+                result.failed.push(local_name.clone());
+            }
+        }
+    }
+    for group in managed {
+        let local_name = local(group);
+        if !current.contains(&local_name)
+            || desired_local.contains(&local_name)
+            || result.removed.contains(&local_name)
+        {
+            continue;
+        }
+        match remove_user_from_group(user, group) {
+            Ok(()) => result.removed.push(local_name),
+            Err(e) => {
+                error!(target:get_log_target(), commit = log_commit(); "Reconcile failed to remove '{}' from '{}': {}", user, group, e);
+                result.failed.push(local_name);
+            }
+        }
+    }
+    info!(target:get_log_target(), commit = log_commit();
+        "Reconciled groups for '{}': {} added, {} removed, {} failed",
+        user,
+        result.added.len(),
+        result.removed.len(),
+        result.failed.len()
+    );
+    Ok(result)
+}
+
 pub fn delete_user(user: &str) -> io::Result<()> {
-    let output = Command::new("sudo")
-        .arg("userdel")
-        .arg("-r")
-        .arg(user)
-        .output()?;
+    validate_username(user)?;
+    match get_user_conf().deletion_policy {
+        DeletionPolicy::HardDelete => hard_delete_user(user),
+        DeletionPolicy::Disable => disable_user(user),
+    }
+}
+
+pub fn hard_delete_user(user: &str) -> io::Result<()> {
+    validate_username(user)?;
+    if is_protected(user, "delete") {
+        return Ok(());
+    }
+    if get_user_conf().dry_run {
+        info!(target:get_log_target(), commit = log_commit(); "[dry-run] Would delete user '{}'.", user);
+        return Ok(());
+    }
+    let output = run_privileged(&["userdel", "-r", user])?;
 
     if output.status.success() {
-        info!(target:get_log_target(), "User '{}' deleted successfully.", user);
+        info!(target:get_log_target(), commit = log_commit(), user = user; "User '{}' deleted successfully.", user);
         Ok(())
     } else {
-        error!(target:get_log_target(),
+        error!(target:get_log_target(), commit = log_commit();
             "Failed to delete user '{}': {}",
             user,
             String::from_utf8_lossy(&output.stderr)
@@ -147,20 +619,887 @@ pub fn delete_user(user: &str) -> io::Result<()> {
     }
 }
 
-pub fn update_user_bashrc(user: &str) -> Result<()> {
-    let bashrc_path = format!("opt/watchdog/users/{}/.bashrc", user);
-    let bashrc_lines = r#"
+/// Disabled users have the nologin shell; `disable_user` sets it and
+/// `enable_user` restores the previous one.
+pub fn is_disabled(user: &str) -> io::Result<bool> {
+    Ok(User::from_name(user)?.is_some_and(|entry| entry.shell.as_os_str() == NOLOGIN_SHELL))
+}
+
+// The marker holds the disable time and, on a second line, the shell to
+// restore if the user is enabled again.
+fn disable_marker(disabled_at: u64, previous_shell: &str) -> String {
+    format!("{}\n{}\n", disabled_at, previous_shell)
+}
+
+fn marker_disabled_at(marker: &str) -> Option<u64> {
+    marker.lines().next()?.trim().parse().ok()
+}
+
+fn marker_previous_shell(marker: &str) -> Option<&str> {
+    marker
+        .lines()
+        .nth(1)
+        .map(str::trim)
+        .filter(|shell| !shell.is_empty() && *shell != NOLOGIN_SHELL)
+}
+
+pub fn disable_user(user: &str) -> io::Result<()> {
+    validate_username(user)?;
+    if is_protected(user, "disable") {
+        return Ok(());
+    }
+    // Disabling again would restart the retention clock (and record nologin
+    // as the shell to restore), so the user would never be purged.
+    if is_disabled(user)? {
+        info!(target:get_log_target(), commit = log_commit(); "User '{}' is already disabled.", user);
+        return Ok(());
+    }
+    if get_user_conf().dry_run {
+        info!(target:get_log_target(), commit = log_commit(); "[dry-run] Would disable user '{}'.", user);
+        return Ok(());
+    }
+    let previous_shell = User::from_name(user)?
+        .map(|entry| entry.shell.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let output = run_privileged(&["usermod", "-L", "-e", "1", "-s", NOLOGIN_SHELL, user])?;
+
+    if !output.status.success() {
+        error!(target:get_log_target(), commit = log_commit();
+            "Failed to disable user '{}': {}",
+            user,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        return Err(io::Error::other("Failed to disable user"));
+    }
+
+    let disabled_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    let marker = disable_marker(disabled_at, &previous_shell);
+    if let Err(e) = write_home_file(user, DISABLED_MARKER, &marker, "644") {
+        error!(target:get_log_target(), commit = log_commit(); "Failed to write disable marker for '{}': {}", user, e);
+    }
+    info!(target:get_log_target(), commit = log_commit(), user = user; "User '{}' disabled, home kept for later purge.", user);
+    Ok(())
+}
+
+/// Undoes `disable_user` for a user granted access again: unlocks the
+/// account, clears the expiry, restores the login shell and removes the
+/// marker so `purge_disabled_users` leaves the user alone.
+pub fn enable_user(user: &str) -> io::Result<()> {
+    validate_username(user)?;
+    if is_protected(user, "enable") {
+        return Ok(());
+    }
+    if get_user_conf().dry_run {
+        info!(target:get_log_target(), commit = log_commit(); "[dry-run] Would re-enable user '{}'.", user);
+        return Ok(());
+    }
+    let marker = read_home_file(user, DISABLED_MARKER)?.unwrap_or_default();
+    let shell = marker_previous_shell(&marker)
+        .map(str::to_string)
+        .or_else(|| get_user_conf().default_shell)
+        .unwrap_or_else(|| DEFAULT_SHELL.to_string());
+    let output = run_privileged(&["usermod", "-U", "-e", "", "-s", &shell, user])?;
+
+    if !output.status.success() {
+        error!(target:get_log_target(), commit = log_commit();
+            "Failed to re-enable user '{}': {}",
+            user,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        return Err(io::Error::other("Failed to re-enable user"));
+    }
+    remove_home_file(user, DISABLED_MARKER)?;
+    info!(target:get_log_target(), commit = log_commit(), user = user; "User '{}' re-enabled with shell '{}'.", user, shell);
+    Ok(())
+}
+
+pub fn purge_disabled_users() -> io::Result<Vec<String>> {
+    let user_conf = get_user_conf();
+    let retention_secs = user_conf.disabled_retention_days * 24 * 60 * 60;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    let mut purged = Vec::new();
+    for entry in fs::read_dir(&user_conf.home_base)? {
+        let entry = entry?;
+        let user = entry.file_name().to_string_lossy().into_owned();
+        if validate_username(&user).is_err() || !user_exists(&user)? {
+            continue;
+        }
+        let Ok(Some(marker)) = read_home_file(&user, DISABLED_MARKER) else {
+            continue;
+        };
+        let Some(disabled_at) = marker_disabled_at(&marker) else {
+            continue;
+        };
+        if now.saturating_sub(disabled_at) < retention_secs {
+            continue;
+        }
+        // A marker left on an account someone re-enabled by hand.
+        if !is_disabled(&user)? {
+            warn!(target:get_log_target(), commit = log_commit(); "User '{}' has a disable marker but is active, not purging.", user);
+            continue;
+        }
+        match hard_delete_user(&user) {
+            Ok(()) => purged.push(user),
+            Err(e) => {
+                error!(target:get_log_target(), commit = log_commit(); "Failed to purge disabled user '{}': {}", user, e);
+            }
+        }
+    }
+    Ok(purged)
+}
+
+pub fn list_managed_users() -> io::Result<Vec<String>> {
+    let user_conf = get_user_conf();
+    let mut users = Vec::new();
+    for entry in fs::read_dir(&user_conf.home_base)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let user = entry.file_name().to_string_lossy().into_owned();
+        if validate_username(&user).is_ok() && !user_conf.protected_users.contains(&user) {
+            users.push(user);
+        }
+    }
+    users.sort();
+    Ok(users)
+}
+
+pub fn rename_user(old_user: &str, new_user: &str) -> io::Result<()> {
+    validate_username(old_user)?;
+    validate_username(new_user)?;
+    if is_protected(old_user, "rename") || is_protected(new_user, "rename to") {
+        return Ok(());
+    }
+    if old_user == new_user {
+        info!(target:get_log_target(), commit = log_commit(); "User '{}' unchanged, nothing to update.", new_user);
+        return Ok(());
+    }
+    if !user_exists(old_user)? {
+        info!(target:get_log_target(), commit = log_commit(); "User '{}' does not exist, nothing to rename.", old_user);
+        return Ok(());
+    }
+    if user_exists(new_user)? {
+        error!(target:get_log_target(), commit = log_commit();
+            "Cannot rename user '{}' to '{}': target already exists.",
+            old_user,
+            new_user
+        );
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("User '{}' already exists", new_user),
+        ));
+    }
+
+    let home_dir = home_dir(new_user);
+    if get_user_conf().dry_run {
+        info!(target:get_log_target(), commit = log_commit(); "[dry-run] Would rename user '{}' to '{}'.", old_user, new_user);
+        return Ok(());
+    }
+    let output = run_privileged(&["usermod", "-l", new_user, "-d", &home_dir, "-m", old_user])?;
+
+    if output.status.success() {
+        info!(target:get_log_target(), commit = log_commit(), user = new_user; "User '{}' renamed to '{}'.", old_user, new_user);
+        Ok(())
+    } else {
+        error!(target:get_log_target(), commit = log_commit();
+            "Failed to rename user '{}' to '{}': {}",
+            old_user,
+            new_user,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        Err(io::Error::other("Failed to rename user"))
+    }
+}
+
+const AUTHORIZED_KEYS: &str = ".ssh/authorized_keys";
+const KEYS_BEGIN: &str = "# watchdog-keys-begin";
+const KEYS_END: &str = "# watchdog-keys-end";
+
+/// Returns `existing` with the block of keys granted through `source` (a
+/// `provider/project`) replaced by `keys`, or removed when `keys` is `None`.
+/// Other sources' blocks and hand-added lines are kept as they are.
+pub fn with_keys_block(existing: &str, source: &str, keys: Option<&str>) -> String {
+    let begin_marker = format!("{} {}", KEYS_BEGIN, source);
+    let end_marker = format!("{} {}", KEYS_END, source);
+    let mut kept = Vec::new();
+    let mut in_block = false;
+    for line in existing.lines() {
+        if line == begin_marker {
+            in_block = true;
+        } else if in_block && line == end_marker {
+            in_block = false;
+        } else if !in_block {
+            kept.push(line);
+        }
+    }
+    let mut updated = kept.join("\n");
+    if !updated.is_empty() {
+        updated.push('\n');
+    }
+    if let Some(keys) = keys.map(str::trim).filter(|keys| !keys.is_empty()) {
+        updated.push_str(&format!("{}\n{}\n{}\n", begin_marker, keys, end_marker));
+    }
+    updated
+}
+
+/// Installs the keys granted through `source` (a `provider/project`), leaving
+/// keys from the user's other grants in place.
+pub fn set_authorized_keys(user: &str, source: &str, keys: &str) -> io::Result<()> {
+    update_authorized_keys(user, source, Some(keys))
+}
+
+/// Removes the keys granted through `source`, keeping every other grant's.
+pub fn clear_authorized_keys(user: &str, source: &str) -> io::Result<()> {
+    update_authorized_keys(user, source, None)
+}
+
+fn update_authorized_keys(user: &str, source: &str, keys: Option<&str>) -> io::Result<()> {
+    validate_username(user)?;
+    if is_protected(user, "change keys for") {
+        return Ok(());
+    }
+    let action = if keys.is_some() { "write" } else { "remove" };
+    if get_user_conf().dry_run {
+        info!(target:get_log_target(), commit = log_commit(); "[dry-run] Would {} authorized keys from {} for '{}'.", action, source, user);
+        return Ok(());
+    }
+    let existing = read_home_file(user, AUTHORIZED_KEYS)?.unwrap_or_default();
+    let updated = with_keys_block(&existing, source, keys);
+    if updated == existing {
+        return Ok(());
+    }
+    write_home_file(user, AUTHORIZED_KEYS, &updated, "600")?;
+    info!(target:get_log_target(), commit = log_commit(), user = user; "Authorized keys from {} for '{}' {}.", source, user, if keys.is_some() { "written" } else { "removed" });
+    Ok(())
+}
+
+// Files inside a home are read and written as its owner (see run_as_user), so
+// a symlink the user plants there leads nowhere they couldn't write already.
+// Writes go to a temporary file that is renamed into place; new directories
+// are created 0700.
+fn write_home_file(user: &str, relative: &str, contents: &str, mode: &str) -> io::Result<()> {
+    let path = format!("{}/{}", home_dir(user), relative);
+    let output = run_as_user(
+        user,
+        &[
+            "sh",
+            "-c",
+            r#"umask 077 && mkdir -p -- "$(dirname -- "$1")" && rm -f -- "$1.watchdog-tmp" && printf '%s' "$2" > "$1.watchdog-tmp" && chmod "$3" -- "$1.watchdog-tmp" && mv -f -- "$1.watchdog-tmp" "$1""#,
+            "sh",
+            &path,
+            contents,
+            mode,
+        ],
+    )?;
+    check_home_command(user, "write", &path, output)
+}
+
+// Exit status 3 marks a missing file.
+fn read_home_file(user: &str, relative: &str) -> io::Result<Option<String>> {
+    let path = format!("{}/{}", home_dir(user), relative);
+    let output = run_as_user(
+        user,
+        &[
+            "sh",
+            "-c",
+            r#"[ -f "$1" ] || exit 3; cat -- "$1""#,
+            "sh",
+            &path,
+        ],
+    )?;
+    if output.status.code() == Some(3) {
+        return Ok(None);
+    }
+    check_home_command(user, "read", &path, output.clone())?;
+    Ok(Some(String::from_utf8_lossy(&output.stdout).into_owned()))
+}
+
+fn remove_home_file(user: &str, relative: &str) -> io::Result<()> {
+    let path = format!("{}/{}", home_dir(user), relative);
+    let output = run_as_user(user, &["rm", "-f", "--", &path])?;
+    check_home_command(user, "remove", &path, output)
+}
+
+fn check_home_command(user: &str, action: &str, path: &str, output: Output) -> io::Result<()> {
+    if output.status.success() {
+        Ok(())
+    } else {
+        error!(target:get_log_target(), commit = log_commit();
+            "Failed to {} '{}' as '{}': {}",
+            action,
+            path,
+            user,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        Err(io::Error::other(format!("Failed to {} '{}'", action, path)))
+    }
+}
+
+fn chown_to_user(user: &str, path: &str) -> io::Result<()> {
+    let output = run_privileged(&["chown", "-R", &format!("{}:", user), path])?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        error!(target:get_log_target(), commit = log_commit();
+            "Failed to chown '{}' to '{}': {}",
+            path,
+            user,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        Err(io::Error::other("Failed to change ownership"))
+    }
+}
+
+const LOADER_BEGIN: &str = "# watchdog-group-loader-begin";
+const LOADER_END: &str = "# watchdog-group-loader-end";
+
+/// Returns `existing` with exactly one group-loader block: an existing block
+/// between the sentinel comments is replaced, otherwise one is appended.
+pub fn with_loader_block(existing: &str) -> String {
+    let block = format!(
+        r#"{LOADER_BEGIN}
 # Load group-specific config if present
 for group in $(id -nG "$USER"); do
     group_bashrc="/home/$group/.bashrc"
     [ -f "$group_bashrc" ] && source "$group_bashrc"
 done
-"#;
-    let mut file = OpenOptions::new()
-        .append(true)
-        .create(true)
-        .open(&bashrc_path)?;
-    file.write_all(bashrc_lines.as_bytes())?;
-    info!(target:get_log_target(), "Appended group-config loader to '{}'.", bashrc_path);
+{LOADER_END}
+"#
+    );
+    if let Some(begin) = existing.find(LOADER_BEGIN)
+        && let Some(end) = existing[begin..].find(LOADER_END)
+    {
+        let mut after = begin + end + LOADER_END.len();
+        if existing[after..].starts_with('\n') {
+            after += 1;
+        }
+        return format!("{}{}{}", &existing[..begin], block, &existing[after..]);
+    }
+    let mut updated = existing.to_string();
+    if !updated.is_empty() && !updated.ends_with('\n') {
+        updated.push('\n');
+    }
+    updated.push('\n');
+    updated.push_str(&block);
+    updated
+}
+
+pub fn update_user_bashrc(user: &str) -> Result<()> {
+    validate_username(user)?;
+    let existing = read_home_file(user, ".bashrc")?.unwrap_or_default();
+    let updated = with_loader_block(&existing);
+    if updated == existing {
+        return Ok(());
+    }
+    write_home_file(user, ".bashrc", &updated, "644")?;
+    info!(target:get_log_target(), commit = log_commit(); "Wrote group-config loader to '{}/.bashrc'.", home_dir(user));
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UserConf;
+    use crate::test_support::{RecordingRunner, global_lock, install, output};
+    use tempfile::TempDir;
+
+    // Home files are written by real shell commands under a temporary home
+    // base; everything privileged is only recorded.
+    fn local_homes() -> (TempDir, std::sync::Arc<RecordingRunner>) {
+        let home_base = TempDir::new().unwrap();
+        let runner = RecordingRunner::with_local_homes();
+        install(
+            &runner,
+            UserConf {
+                home_base: home_base.path().to_string_lossy().into_owned(),
+                ..UserConf::default()
+            },
+        );
+        (home_base, runner)
+    }
+
+    #[test]
+    fn accepts_plain_usernames() {
+        for name in ["alice", "_svc", "bob-2", "a_b_c"] {
+            assert!(validate_username(name).is_ok(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn rejects_unsafe_usernames() {
+        for name in [
+            "",
+            "foo; rm -rf",
+            "--uid=0",
+            "-o",
+            "Alice",
+            "1abc",
+            "a b",
+            "../root",
+            "abcdefghijklmnopqrstuvwxyz0123456",
+        ] {
+            let err = validate_username(name).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn bashrc_is_written_inside_the_users_home() {
+        let _guard = global_lock();
+        let (home_base, _runner) = local_homes();
+
+        update_user_bashrc("alice").unwrap();
+
+        let bashrc = home_base.path().join("alice/.bashrc");
+        assert!(bashrc.is_file());
+        assert!(fs::read_to_string(bashrc).unwrap().contains(LOADER_BEGIN));
+        assert!(!home_base.path().join("alice.bashrc").exists());
+    }
+
+    #[test]
+    fn bashrc_keeps_one_loader_block_across_runs() {
+        let _guard = global_lock();
+        let (home_base, _runner) = local_homes();
+
+        update_user_bashrc("alice").unwrap();
+        update_user_bashrc("alice").unwrap();
+
+        let bashrc = fs::read_to_string(home_base.path().join("alice/.bashrc")).unwrap();
+        assert_eq!(bashrc.matches(LOADER_BEGIN).count(), 1);
+        assert_eq!(bashrc.matches(LOADER_END).count(), 1);
+    }
+
+    #[test]
+    fn loader_block_replaces_an_existing_one_in_place() {
+        let once = with_loader_block("export A=1\n");
+        let twice = with_loader_block(&format!("{}export B=2\n", once));
+        assert_eq!(twice.matches(LOADER_BEGIN).count(), 1);
+        assert!(twice.starts_with("export A=1\n"));
+        assert!(twice.contains("export B=2"));
+    }
+
+    #[test]
+    fn authorized_keys_are_private_and_kept_per_grant() {
+        use std::os::unix::fs::PermissionsExt;
+        let _guard = global_lock();
+        let (home_base, _runner) = local_homes();
+
+        set_authorized_keys("alice", "aws/proj", "ssh-ed25519 AAAA one").unwrap();
+        set_authorized_keys("alice", "gcp/other", "ssh-ed25519 BBBB two").unwrap();
+        clear_authorized_keys("alice", "aws/proj").unwrap();
+
+        let ssh = home_base.path().join("alice/.ssh");
+        let keys = ssh.join("authorized_keys");
+        let mode =
+            |path: &std::path::Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&ssh), 0o700);
+        assert_eq!(mode(&keys), 0o600);
+        let contents = fs::read_to_string(keys).unwrap();
+        assert!(!contents.contains("AAAA"));
+        assert!(contents.contains("ssh-ed25519 BBBB two"));
+    }
+
+    #[test]
+    fn authorized_keys_write_replaces_a_planted_symlink() {
+        let _guard = global_lock();
+        let (home_base, _runner) = local_homes();
+        let victim = home_base.path().join("victim");
+        fs::write(&victim, "untouched").unwrap();
+        fs::create_dir_all(home_base.path().join("alice/.ssh")).unwrap();
+        std::os::unix::fs::symlink(&victim, home_base.path().join("alice/.ssh/authorized_keys"))
+            .unwrap();
+
+        set_authorized_keys("alice", "aws/proj", "ssh-ed25519 AAAA").unwrap();
+
+        assert_eq!(fs::read_to_string(&victim).unwrap(), "untouched");
+        let keys = home_base.path().join("alice/.ssh/authorized_keys");
+        assert!(!fs::symlink_metadata(keys).unwrap().file_type().is_symlink());
+    }
+
+    #[test]
+    fn keys_blocks_replace_only_their_own_source() {
+        let existing = with_keys_block("ssh-rsa MANUAL\n", "aws/proj", Some("ssh-ed25519 OLD"));
+        let updated = with_keys_block(&existing, "aws/proj", Some("ssh-ed25519 NEW"));
+        assert!(updated.contains("ssh-rsa MANUAL"));
+        assert!(updated.contains("ssh-ed25519 NEW"));
+        assert!(!updated.contains("OLD"));
+        assert_eq!(
+            with_keys_block(&updated, "aws/proj", None).trim(),
+            "ssh-rsa MANUAL"
+        );
+    }
+
+    fn recorded(user_conf: UserConf) -> std::sync::Arc<RecordingRunner> {
+        let runner = RecordingRunner::new();
+        install(&runner, user_conf);
+        runner
+    }
+
+    #[test]
+    fn missing_groups_are_created_when_enabled() {
+        let _guard = global_lock();
+        let runner = recorded(UserConf {
+            auto_create_groups: true,
+            ..UserConf::default()
+        });
+
+        add_user_to_group("alice", "watchdog-newgroup").unwrap();
+
+        let commands = runner.commands();
+        let groupadd = commands
+            .iter()
+            .position(|c| c == "sudo groupadd watchdog-newgroup");
+        let usermod = commands
+            .iter()
+            .position(|c| c == "sudo usermod -aG watchdog-newgroup alice");
+        assert!(groupadd.is_some() && usermod.is_some(), "{:?}", commands);
+        assert!(groupadd < usermod);
+    }
+
+    #[test]
+    fn missing_groups_are_an_error_by_default() {
+        let _guard = global_lock();
+        let runner = recorded(UserConf::default());
+
+        let err = add_user_to_group("alice", "watchdog-newgroup").unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(!runner.ran_starting_with("sudo groupadd"));
+        assert!(!runner.ran_starting_with("sudo usermod -aG"));
+    }
+
+    #[test]
+    fn invalid_group_names_are_never_created() {
+        let _guard = global_lock();
+        let runner = recorded(UserConf::default());
+        assert!(create_group("bad;group").is_err());
+        assert!(runner.commands().is_empty());
+    }
+
+    #[test]
+    fn group_list_is_deduplicated_in_order() {
+        let groups: Vec<String> = ["b", "a", "b", "c", "a"].map(String::from).to_vec();
+        assert_eq!(group_list_arg(&groups), "b,a,c");
+    }
+
+    // root stands in for an existing account that is already in group root.
+    #[test]
+    fn missing_groups_are_added_in_one_usermod() {
+        let _guard = global_lock();
+        let runner = recorded(UserConf {
+            auto_create_groups: true,
+            ..UserConf::default()
+        });
+        let groups: Vec<String> = ["root", "proj-b", "proj-c", "proj-b"]
+            .map(String::from)
+            .to_vec();
+
+        let outcomes = add_user_to_groups("root", &groups).unwrap();
+
+        let usermods: Vec<String> = runner
+            .commands()
+            .into_iter()
+            .filter(|c| c.starts_with("sudo usermod"))
+            .collect();
+        assert_eq!(usermods, vec!["sudo usermod -aG proj-b,proj-c root"]);
+        let added: Vec<bool> = outcomes.iter().map(|o| o.group_added()).collect();
+        assert_eq!(added, vec![false, true, true, true]);
+    }
+
+    // Whether sudo or wheel exist varies by host, so the sudo -> wheel alias
+    // is mirrored with groups that exist nowhere.
+    #[test]
+    fn forbidden_groups_are_checked_after_alias_resolution() {
+        let _guard = global_lock();
+        let runner = recorded(UserConf {
+            auto_create_groups: true,
+            group_aliases: [("watchdog-sudo".to_string(), "watchdog-wheel".to_string())].into(),
+            forbidden_groups: vec!["watchdog-wheel".to_string()],
+            ..UserConf::default()
+        });
+
+        let err = add_user_to_group("alice", "watchdog-sudo").unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(!runner.ran_starting_with("sudo groupadd"));
+        assert!(!runner.ran_starting_with("sudo usermod"));
+    }
+
+    #[test]
+    fn managed_users_are_the_valid_directories_under_home_base() {
+        let _guard = global_lock();
+        let home_base = TempDir::new().unwrap();
+        for dir in ["bob", "alice", "Not-A-User", "carol"] {
+            fs::create_dir(home_base.path().join(dir)).unwrap();
+        }
+        fs::write(home_base.path().join("dave"), "a file, not a home").unwrap();
+        recorded(UserConf {
+            home_base: home_base.path().to_string_lossy().into_owned(),
+            protected_users: vec!["carol".to_string()],
+            ..UserConf::default()
+        });
+
+        assert_eq!(list_managed_users().unwrap(), vec!["alice", "bob"]);
+    }
+
+    // The chown is privileged and only recorded; the chmods run for real.
+    #[test]
+    fn home_ownership_is_handed_to_the_user_with_private_modes() {
+        use std::os::unix::fs::PermissionsExt;
+        let _guard = global_lock();
+        let (home_base, runner) = local_homes();
+        let home = home_base.path().join("alice");
+        fs::create_dir_all(home.join(".ssh")).unwrap();
+        fs::write(home.join(".bashrc"), "").unwrap();
+
+        fix_home_ownership("alice").unwrap();
+
+        assert!(runner.ran(&format!("sudo chown -R alice: {}", home.display())));
+        let mode =
+            |path: &std::path::Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&home), 0o750);
+        assert_eq!(mode(&home.join(".bashrc")), 0o644);
+        assert_eq!(mode(&home.join(".ssh")), 0o700);
+    }
+
+    #[test]
+    fn useradd_args_default_to_home_and_skel() {
+        let _guard = global_lock();
+        recorded(UserConf::default());
+        assert_eq!(
+            useradd_args("alice", "/home/alice"),
+            [
+                "useradd",
+                "-m",
+                "-d",
+                "/home/alice",
+                "--skel",
+                "/etc/skel",
+                "alice"
+            ]
+        );
+    }
+
+    #[test]
+    fn useradd_args_include_shell_group_and_extra_flags() {
+        let _guard = global_lock();
+        recorded(UserConf {
+            default_shell: Some("/usr/sbin/nologin".to_string()),
+            primary_group: Some("sftp".to_string()),
+            extra_useradd_args: vec!["-c".to_string(), "managed".to_string()],
+            ..UserConf::default()
+        });
+        assert_eq!(
+            useradd_args("alice", "/home/alice"),
+            [
+                "useradd",
+                "-m",
+                "-d",
+                "/home/alice",
+                "--skel",
+                "/etc/skel",
+                "-s",
+                "/usr/sbin/nologin",
+                "-g",
+                "sftp",
+                "-c",
+                "managed",
+                "alice"
+            ]
+        );
+    }
+
+    // Fails the privileged `command` (e.g. useradd) with the given exit code
+    // and stderr; everything else succeeds.
+    fn command_fails_with(
+        command: &'static str,
+        code: i32,
+        stderr: &'static str,
+    ) -> std::sync::Arc<RecordingRunner> {
+        let runner = RecordingRunner::with_responder(move |program, args| match (program, args) {
+            ("sudo", [run, ..]) if *run == command => Output {
+                stderr: stderr.as_bytes().to_vec(),
+                ..output(code, "")
+            },
+            _ => output(0, ""),
+        });
+        install(
+            &runner,
+            UserConf {
+                auto_create_groups: true,
+                ..UserConf::default()
+            },
+        );
+        runner
+    }
+
+    // nobody stands in for an account another sync created first.
+    #[test]
+    fn useradd_races_with_an_existing_user_are_not_errors() {
+        let _guard = global_lock();
+        for (code, stderr) in [
+            (9, "useradd: user 'nobody' already exists"),
+            (1, "useradd: user 'nobody' already exists"),
+        ] {
+            let runner = command_fails_with("useradd", code, stderr);
+
+            create_user("nobody").unwrap();
+
+            assert!(runner.ran_starting_with("sudo useradd"));
+            assert!(!runner.ran_starting_with("sudo -u nobody"));
+        }
+    }
+
+    #[test]
+    fn name_taken_without_a_user_is_an_error() {
+        let _guard = global_lock();
+        for (code, stderr) in [
+            (
+                9,
+                "useradd: group alice exists - if you want to add this user to that group, use -g.",
+            ),
+            (1, "useradd: user 'alice' already exists"),
+        ] {
+            let runner = command_fails_with("useradd", code, stderr);
+
+            assert!(add_user_to_group("alice", "proj").is_err());
+            assert!(!runner.ran_starting_with("sudo usermod"));
+            assert!(!runner.ran_starting_with("sudo -u alice"));
+        }
+    }
+
+    #[test]
+    fn other_useradd_failures_are_errors() {
+        let _guard = global_lock();
+        let runner = command_fails_with("useradd", 1, "useradd: cannot lock /etc/passwd");
+
+        assert!(add_user_to_group("alice", "proj").is_err());
+        assert!(!runner.ran_starting_with("sudo usermod"));
+    }
+
+    #[test]
+    fn sudo_maps_to_wheel_only_when_the_fallback_is_on() {
+        let _guard = global_lock();
+        recorded(UserConf::default());
+        assert_eq!(local_group_name("sudo"), "wheel");
+
+        recorded(UserConf {
+            sudo_wheel_fallback: false,
+            ..UserConf::default()
+        });
+        assert_eq!(local_group_name("sudo"), "sudo");
+
+        recorded(UserConf {
+            sudo_wheel_fallback: false,
+            group_aliases: [("sudo".to_string(), "admins".to_string())].into(),
+            ..UserConf::default()
+        });
+        assert_eq!(local_group_name("sudo"), "admins");
+    }
+
+    #[test]
+    fn removing_a_non_member_is_a_no_op() {
+        let _guard = global_lock();
+        let runner = command_fails_with(
+            "gpasswd",
+            3,
+            "gpasswd: user 'alice' is not a member of 'proj'",
+        );
+
+        remove_user_from_group("alice", "proj").unwrap();
+
+        assert!(runner.ran("sudo gpasswd -d alice proj"));
+    }
+
+    #[test]
+    fn other_gpasswd_failures_are_errors() {
+        let _guard = global_lock();
+        command_fails_with("gpasswd", 1, "gpasswd: cannot lock /etc/group");
+        assert!(remove_user_from_group("alice", "proj").is_err());
+    }
+
+    #[test]
+    fn protected_users_are_never_changed() {
+        let _guard = global_lock();
+        for policy in [DeletionPolicy::HardDelete, DeletionPolicy::Disable] {
+            let runner = recorded(UserConf {
+                auto_create_groups: true,
+                deletion_policy: policy,
+                protected_users: vec!["root".to_string(), "alice".to_string()],
+                ..UserConf::default()
+            });
+
+            create_user("alice").unwrap();
+            add_user_to_group("root", "proj").unwrap();
+            add_user_to_groups("root", &["proj".to_string()]).unwrap();
+            remove_user_from_group("root", "proj").unwrap();
+            set_primary_group("root", "proj").unwrap();
+            set_login_shell("root", "/bin/sh").unwrap();
+            delete_user("root").unwrap();
+            enable_user("root").unwrap();
+            rename_user("root", "admin").unwrap();
+            rename_user("nobody", "alice").unwrap();
+            set_authorized_keys("root", "aws/proj", "ssh-ed25519 AAAA").unwrap();
+            clear_authorized_keys("root", "aws/proj").unwrap();
+
+            assert!(runner.commands().is_empty(), "{:?}", runner.commands());
+        }
+    }
+
+    #[test]
+    fn group_add_reports_created_added_and_already_member() {
+        let _guard = global_lock();
+        recorded(UserConf {
+            auto_create_groups: true,
+            ..UserConf::default()
+        });
+
+        assert_eq!(
+            add_user_to_group("alice", "proj").unwrap(),
+            GroupAddOutcome::CreatedAndAdded
+        );
+        assert_eq!(
+            add_user_to_group("root", "root").unwrap(),
+            GroupAddOutcome::AlreadyMember
+        );
+        assert_eq!(
+            add_user_to_group("root", "other").unwrap(),
+            GroupAddOutcome::AddedToGroup
+        );
+    }
+
+    #[test]
+    fn granting_a_new_user_issues_exactly_these_commands() {
+        let _guard = global_lock();
+        let runner = recorded(UserConf {
+            auto_create_groups: true,
+            ..UserConf::default()
+        });
+
+        add_user_to_group("alice", "proj").unwrap();
+
+        // Home-file writes run as the user and are covered above.
+        let privileged: Vec<String> = runner
+            .commands()
+            .into_iter()
+            .filter(|c| !c.starts_with("sudo -u alice --"))
+            .collect();
+        assert_eq!(
+            privileged,
+            [
+                "sudo useradd -m -d /opt/watchdog/users/alice --skel /etc/skel alice",
+                "sudo chown -R alice: /opt/watchdog/users/alice",
+                "sudo groupadd proj",
+                "sudo usermod -aG proj alice",
+            ]
+        );
+    }
+}
@@ -0,0 +1,82 @@
+use crate::config::{KeyhouseConf, get_log_target, log_commit, set_log_target};
+use crate::services::commit_store::FileCommitStore;
+use crate::services::github_service::process_update_request;
+use hmac::{Hmac, Mac};
+use log::{error, info, warn};
+use reqwest::StatusCode;
+use sha2::Sha256;
+
+pub fn verify_signature(secret: &[u8], body: &[u8], header: &str) -> bool {
+    let Some(hex_signature) = header.trim().strip_prefix("sha256=") else {
+        return false;
+    };
+    let Ok(signature) = hex::decode(hex_signature) else {
+        return false;
+    };
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret) else {
+        return false;
+    };
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
+
+pub async fn handle_push_event(
+    keyhouse_config: KeyhouseConf,
+    update_log_target: &str,
+    hostname: String,
+    signature_header: Option<&str>,
+    body: &[u8],
+) -> StatusCode {
+    set_log_target(update_log_target.to_string());
+    let Some(secret) = keyhouse_config.webhook_secret.clone() else {
+        error!(target:get_log_target(), commit = log_commit(); "Received webhook but no webhook_secret is configured");
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    };
+    let Some(signature_header) = signature_header else {
+        warn!(target:get_log_target(), commit = log_commit(); "Rejecting webhook without X-Hub-Signature-256 header");
+        return StatusCode::UNAUTHORIZED;
+    };
+    if !verify_signature(secret.expose().as_bytes(), body, signature_header) {
+        warn!(target:get_log_target(), commit = log_commit(); "Rejecting webhook with invalid signature");
+        return StatusCode::UNAUTHORIZED;
+    }
+    info!(target:get_log_target(), commit = log_commit(); "Webhook signature verified, starting sync...");
+    let commit_store = FileCommitStore::new(keyhouse_config.commit_file_path());
+    match process_update_request(keyhouse_config, update_log_target, hostname, &commit_store).await
+    {
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            error!(target:get_log_target(), commit = log_commit(); "Webhook-triggered sync failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example from GitHub's webhook validation docs.
+    const SECRET: &[u8] = b"It's a Secret to Everybody";
+    const BODY: &[u8] = b"Hello, World!";
+    const SIGNATURE: &str =
+        "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+
+    #[test]
+    fn accepts_a_known_good_signature() {
+        assert!(verify_signature(SECRET, BODY, SIGNATURE));
+    }
+
+    #[test]
+    fn rejects_a_tampered_body() {
+        assert!(!verify_signature(SECRET, b"Hello, World?", SIGNATURE));
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        assert!(!verify_signature(SECRET, BODY, ""));
+        assert!(!verify_signature(SECRET, BODY, "sha1=757107ea"));
+        assert!(!verify_signature(SECRET, BODY, "sha256=not-hex"));
+        assert!(!verify_signature(b"another secret", BODY, SIGNATURE));
+    }
+}
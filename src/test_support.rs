@@ -0,0 +1,102 @@
+//! Fixtures for tests that touch process-global settings (`UserConf`, the
+//! command runner, the privilege command, the log target). Those tests hold
+//! `global_lock()` so parallel tests never see each other's settings.
+
+use crate::config::{UserConf, set_user_conf};
+use crate::services::command_runner::{
+    CommandRunner, set_command_rate, set_command_runner, set_privilege_command,
+};
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Command, ExitStatus, Output};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+static GLOBAL: Mutex<()> = Mutex::new(());
+
+pub(crate) fn global_lock() -> MutexGuard<'static, ()> {
+    // A failed test must not poison every test after it.
+    GLOBAL
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+pub(crate) fn output(code: i32, stdout: &str) -> Output {
+    Output {
+        status: ExitStatus::from_raw(code << 8),
+        stdout: stdout.as_bytes().to_vec(),
+        stderr: Vec::new(),
+    }
+}
+
+type Responder = Box<dyn Fn(&str, &[&str]) -> Output + Send + Sync>;
+
+/// Records every command instead of running it, answering from `respond`
+/// (success with no output by default).
+pub(crate) struct RecordingRunner {
+    calls: Mutex<Vec<Vec<String>>>,
+    respond: Responder,
+}
+
+impl RecordingRunner {
+    pub(crate) fn new() -> Arc<Self> {
+        Self::with_responder(|_, _| output(0, ""))
+    }
+
+    pub(crate) fn with_responder(
+        respond: impl Fn(&str, &[&str]) -> Output + Send + Sync + 'static,
+    ) -> Arc<Self> {
+        Arc::new(RecordingRunner {
+            calls: Mutex::new(Vec::new()),
+            respond: Box::new(respond),
+        })
+    }
+
+    /// Runs commands for `sudo -u <user> --` for real (as the current user),
+    /// so home-file helpers can be checked against a temporary home base;
+    /// everything else is only recorded.
+    pub(crate) fn with_local_homes() -> Arc<Self> {
+        Self::with_responder(|program, args| match (program, args) {
+            ("sudo", ["-u", _, "--", command, rest @ ..]) => Command::new(command)
+                .args(rest)
+                .output()
+                .expect("failed to run home command"),
+            _ => output(0, ""),
+        })
+    }
+
+    /// Every command run, program first, e.g. `sudo usermod -aG proj alice`.
+    pub(crate) fn commands(&self) -> Vec<String> {
+        self.calls
+            .lock()
+            .expect("runner lock poisoned")
+            .iter()
+            .map(|call| call.join(" "))
+            .collect()
+    }
+
+    pub(crate) fn ran(&self, command: &str) -> bool {
+        self.commands().iter().any(|run| run == command)
+    }
+
+    pub(crate) fn ran_starting_with(&self, prefix: &str) -> bool {
+        self.commands().iter().any(|run| run.starts_with(prefix))
+    }
+}
+
+impl CommandRunner for RecordingRunner {
+    fn run(&self, program: &str, args: &[&str]) -> io::Result<Output> {
+        let mut call = vec![program.to_string()];
+        call.extend(args.iter().map(|arg| arg.to_string()));
+        self.calls.lock().expect("runner lock poisoned").push(call);
+        Ok((self.respond)(program, args))
+    }
+}
+
+/// Installs `runner` with `sudo` as the privilege command, no rate limit and
+/// the given user settings.
+pub(crate) fn install(runner: &Arc<RecordingRunner>, user_conf: UserConf) {
+    set_command_runner(runner.clone());
+    set_privilege_command(Some("sudo".to_string()));
+    set_command_rate(None);
+    set_user_conf(user_conf);
+}